@@ -1,11 +1,1004 @@
 //! USB device tree explorer CLI application
 
-use anyhow::Result;
-use usbinfo::{usb_tree, TreeFormatter};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_signal::{Signal, Signals};
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use futures_lite::future::{self, block_on};
+use futures_lite::StreamExt;
+use usbinfo::{
+    class_name, speed_name, usb_tree, ColorMode, Column, Config, Debouncer, DevicePath,
+    DeviceLocation, DeviceWait, EventKind, EventRecord, EventSource, Filter, FlapThreshold,
+    FlapTracker, Hook, Labels, ListFormatter, LiveTree, Manifest, OutputFormat, PortMap, Recorder,
+    Replay, Settings, SystemEvents, TreeChange, TreeEvent, TreeFormatter, UdevIdPath, UsbDevice,
+    UsbTree, UsbTreeError, VidPidFilter, WaitOutcome,
+};
+
+/// Explore the USB device tree
+#[derive(Debug, Parser)]
+#[command(name = "usbinfo", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Apply a named profile from the config file
+    #[arg(short = 'P', long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Read the config from FILE instead of the default location
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Output format: tree or list
+    #[arg(short, long)]
+    format: Option<OutputFormat>,
+
+    /// Only show devices matching VID:PID patterns (repeatable or comma-separated),
+    /// e.g. 0403:*, 0483:5700-57ff, 2e8a:0000/ff00 or !1d6b:*
+    #[arg(
+        long = "vid-pid",
+        value_name = "VID:PID",
+        value_delimiter = ',',
+        allow_hyphen_values = true,
+        global = true
+    )]
+    vid_pid: Vec<String>,
+
+    /// Only show devices matching a filter expression,
+    /// e.g. 'vid=0x0483 and (class=hid or serial~"^ABC") and speed>=high and under 1:2'
+    #[arg(short = 'w', long = "where", value_name = "EXPR", global = true)]
+    filter: Option<String>,
+
+    /// Columns for list output (comma-separated)
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',')]
+    columns: Vec<Column>,
+
+    /// When to use colors: auto, always or never
+    #[arg(long, value_name = "WHEN")]
+    color: Option<ColorMode>,
+
+    /// Load a color theme from a TOML file
+    #[arg(long, value_name = "FILE")]
+    theme: Option<PathBuf>,
+
+    /// Use ASCII connectors instead of Unicode box drawing
+    #[arg(long)]
+    ascii: bool,
+
+    /// Use Unicode box drawing, even if the config asks for ASCII
+    #[arg(long, conflicts_with = "ascii")]
+    unicode: bool,
+
+    /// Do not print headers: bus lines in trees, column names in lists
+    #[arg(long)]
+    no_header: bool,
+
+    /// Print headers, even if the config turns them off
+    #[arg(long, conflicts_with = "no_header")]
+    header: bool,
+
+    /// Read device labels from FILE
+    #[arg(long, value_name = "FILE", global = true)]
+    labels: Option<PathBuf>,
+
+    /// Read the physical port map from FILE
+    #[arg(long, value_name = "FILE", global = true)]
+    ports: Option<PathBuf>,
+
+    /// Record the hotplug session of watch, events, wait or on to FILE
+    #[arg(long, value_name = "FILE", global = true, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay a recorded hotplug session instead of following this machine
+    #[arg(long, value_name = "FILE", global = true)]
+    replay: Option<PathBuf>,
+
+    /// Replay FACTOR times as fast as recorded, or "inf" for no delays
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        global = true,
+        requires = "replay",
+        value_parser = parse_speed_factor
+    )]
+    speed: Option<f64>,
+}
+
+/// Parse a positive replay speed factor
+fn parse_speed_factor(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed > 0.0 => Ok(speed),
+        _ => Err(format!("'{}' is not a positive number", s)),
+    }
+}
+
+/// Where hotplug events come from
+enum Source {
+    /// This machine
+    System(SystemEvents),
+    /// This machine, recorded to a file
+    Recorded(Recorder<SystemEvents, File>),
+    /// A recording
+    Replay(Replay),
+}
+
+impl Source {
+    /// Report whether the recording, if any, was written completely
+    fn finish(self) -> Result<()> {
+        if let Source::Recorded(recorder) = self {
+            recorder.finish().context("writing recording")?;
+        }
+        Ok(())
+    }
+}
+
+impl EventSource for Source {
+    async fn snapshot(&mut self) -> Result<Vec<UsbDevice>, UsbTreeError> {
+        match self {
+            Source::System(events) => events.snapshot().await,
+            Source::Recorded(events) => events.snapshot().await,
+            Source::Replay(events) => events.snapshot().await,
+        }
+    }
+
+    async fn next_event(&mut self) -> Option<TreeEvent> {
+        match self {
+            Source::System(events) => events.next_event().await,
+            Source::Recorded(events) => events.next_event().await,
+            Source::Replay(events) => events.next_event().await,
+        }
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        match self {
+            Source::System(events) => events.timestamp(),
+            Source::Recorded(events) => events.timestamp(),
+            Source::Replay(events) => events.timestamp(),
+        }
+    }
+
+    fn speed(&self) -> f64 {
+        match self {
+            Source::System(events) => events.speed(),
+            Source::Recorded(events) => events.speed(),
+            Source::Replay(events) => events.speed(),
+        }
+    }
+}
+
+/// Why [`follow`] returned
+enum Followed {
+    /// A callback asked to stop
+    Stopped,
+    /// The timeout expired
+    TimedOut,
+    /// The event source ran out, e.g. at the end of a replay
+    Ended,
+    /// Ctrl-C was pressed
+    Interrupted,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Show details and the subtree of one device
+    Show {
+        /// Device path (e.g. 1:2.3, 1-2.3, "Bus 001 Device 004") or label (e.g. @dut-relay)
+        path: String,
+    },
+    /// Print which physical port a device is plugged into
+    Locate {
+        /// Device path (e.g. 1:2.3, 1-2.3, "Bus 001 Device 004") or label (e.g. @dut-relay)
+        path: String,
+    },
+    /// Print the tree, then follow devices as they are plugged and unplugged
+    ///
+    /// Counts reconnects per port and per device, flags the ones that
+    /// disconnect too often, and prints a summary when watching ends. With
+    /// --for, exits with 2 if anything was flapping.
+    Watch {
+        /// Stop after this long and print the reconnect summary, e.g. 10m
+        #[arg(long = "for", value_name = "DURATION", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+        /// Flag a port or device after this many disconnects within --flap-window
+        #[arg(
+            long,
+            value_name = "COUNT",
+            default_value_t = 3,
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        flap_threshold: u32,
+        /// Time window for --flap-threshold
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = humantime::parse_duration,
+            default_value = "1m"
+        )]
+        flap_window: Duration,
+        /// Keep a JSON reconnect report in FILE, updated on every change
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+    /// Print one line per device attach or detach
+    Events {
+        /// Output format: text or jsonl (one JSON object per event)
+        #[arg(short, long, value_name = "FORMAT", default_value = "text")]
+        format: EventFormat,
+    },
+    /// Block until a matching device appears (or disappears) and print its path
+    ///
+    /// Exits with 0 once the condition is met and 124 on timeout. Matches
+    /// devices against --vid-pid and --where as well as the options below.
+    Wait {
+        /// Only match the device with this serial number
+        #[arg(long)]
+        serial: Option<String>,
+        /// Only match devices at paths matching this pattern, e.g. 1:2.*, or
+        /// the device of a label, e.g. @dut-relay
+        #[arg(long, value_name = "PATTERN")]
+        path: Option<DeviceLocation>,
+        /// Wait until no matching device is connected instead
+        #[arg(long)]
+        gone: bool,
+        /// Give up after this long, e.g. 30s or 2m
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+    /// Check the connected devices against a manifest of expected ones
+    ///
+    /// Prints a pass/fail line per expected device and exits with 2 if any
+    /// is missing or does not meet its requirements, or with 1 if the
+    /// manifest cannot be read.
+    Check {
+        /// Manifest file listing the expected devices
+        manifest: PathBuf,
+    },
+    /// Run a command whenever a matching device attaches or detaches
+    ///
+    /// The command sees the device in USBINFO_* environment variables, such
+    /// as USBINFO_EVENT, USBINFO_PATH, USBINFO_SERIAL and USBINFO_VIDPID.
+    /// Without a command, runs the [[hook]] entries of the config file,
+    /// which set their own triggers and debounce; --vid-pid and --where
+    /// still narrow the devices they run for.
+    On {
+        /// Only run when a device attaches
+        #[arg(long, conflicts_with = "detach", requires = "command")]
+        attach: bool,
+        /// Only run when a device detaches
+        #[arg(long, requires = "command")]
+        detach: bool,
+        /// Run once a device has been quiet this long, for its last event only
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = humantime::parse_duration,
+            default_value = "0s",
+            requires = "command"
+        )]
+        debounce: Duration,
+        /// Program and arguments to run, after --
+        #[arg(last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+}
+
+/// Exit status of `wait` when the timeout expires, as with timeout(1)
+const EXIT_TIMEOUT: i32 = 124;
+
+/// Exit status of `check` when the machine does not match the manifest,
+/// distinct from the 1 of any error
+const EXIT_DRIFT: i32 = 2;
+
+/// Exit status of `watch --for` when a port or device was flapping, like
+/// [`EXIT_DRIFT`] distinct from the 1 of any error
+const EXIT_FLAPPING: i32 = 2;
+
+/// How `events` prints each event
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EventFormat {
+    /// The same lines as `watch`
+    Text,
+    /// JSON Lines with timestamp, event type, path and device record
+    Jsonl,
+}
+
+impl Cli {
+    /// Settings given explicitly on the command line
+    fn settings(&self) -> Settings {
+        Settings {
+            ascii: (self.ascii || self.unicode).then_some(self.ascii),
+            color: self.color,
+            theme: self.theme.clone(),
+            header: (self.header || self.no_header).then_some(self.header),
+            format: self.format,
+            vid_pid: (!self.vid_pid.is_empty()).then(|| self.vid_pid.clone()),
+            filter: self.filter.clone(),
+            columns: (!self.columns.is_empty()).then(|| self.columns.clone()),
+            labels: self.labels.clone(),
+            ports: self.ports.clone(),
+        }
+    }
+
+    /// Load the config file given on the command line or the default one
+    fn config(&self) -> Result<Config> {
+        Ok(match self.config {
+            Some(ref path) => Config::from_file(path)?,
+            None => Config::load()?,
+        })
+    }
+
+    /// Open the hotplug event source selected by --record and --replay
+    fn event_source(&self) -> Result<Source> {
+        if let Some(ref path) = self.replay {
+            let replay = Replay::from_file(path)
+                .with_context(|| format!("loading recording '{}'", path.display()))?;
+            return Ok(Source::Replay(replay.with_speed(self.speed.unwrap_or(1.0))));
+        }
+        let events = SystemEvents::new()?;
+        match self.record {
+            Some(ref path) => {
+                let file = File::create(path)
+                    .with_context(|| format!("creating recording '{}'", path.display()))?;
+                Ok(Source::Recorded(Recorder::new(events, file)))
+            }
+            None => Ok(Source::System(events)),
+        }
+    }
+
+    /// Merge the config's defaults, the selected profile and the flags
+    fn resolve(&self, config: &Config) -> Result<Settings> {
+        let settings = config.settings(self.profile.as_deref())?;
+        Ok(settings.merge(self.settings()))
+    }
+}
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = cli.config()?;
+    let settings = cli.resolve(&config)?;
+    settings.color.unwrap_or_default().apply();
+
+    let labels = settings.labels()?;
+    let ports = settings.port_map()?;
+    match cli.command {
+        Some(Command::Watch {
+            duration,
+            flap_threshold,
+            flap_window,
+            ref report,
+        }) => {
+            let threshold = FlapThreshold {
+                disconnects: flap_threshold as usize,
+                window: flap_window,
+            };
+            let source = cli.event_source()?;
+            return watch(&settings, source, &labels, &ports, duration, threshold, report.as_deref());
+        }
+        Some(Command::Events { format }) => {
+            return events(&settings, cli.event_source()?, &labels, format);
+        }
+        Some(Command::Wait {
+            ref serial,
+            ref path,
+            gone,
+            timeout,
+        }) => {
+            let source = cli.event_source()?;
+            return wait(&settings, source, &labels, serial.as_deref(), path.as_ref(), gone, timeout);
+        }
+        Some(Command::On {
+            attach,
+            detach,
+            debounce,
+            ref command,
+        }) => {
+            let trigger = match (attach, detach) {
+                (true, _) => Some(EventKind::Attach),
+                (_, true) => Some(EventKind::Detach),
+                _ => None,
+            };
+            let hook = Hook {
+                on: trigger,
+                run: command.clone(),
+                debounce,
+                ..Default::default()
+            };
+            let hooks = if command.is_empty() { config.hooks } else { vec![hook] };
+            return on(&settings, cli.event_source()?, hooks, &labels);
+        }
+        _ => {}
+    }
+    if cli.record.is_some() || cli.replay.is_some() {
+        bail!("--record and --replay only apply to watch, events, wait and on");
+    }
     let tree = usb_tree()?;
-    let formatter = TreeFormatter::new(&tree);
-    print!("{}", formatter);
+
+    match cli.command {
+        None => print_tree(&tree, &settings, &labels, &ports),
+        Some(Command::Show { ref path }) => {
+            let path = labels.resolve_path(path, &tree)?;
+            show(&tree, &path, &settings, &labels, &ports)
+        }
+        Some(Command::Locate { ref path }) => {
+            let path = labels.resolve_path(path, &tree)?;
+            locate(&path, &ports)
+        }
+        Some(Command::Check { ref manifest }) => {
+            if !cli.vid_pid.is_empty() || cli.filter.is_some() {
+                bail!("check selects devices by its manifest entries and does not take --vid-pid or --where");
+            }
+            check(&tree, manifest, &labels)
+        }
+        Some(Command::Watch { .. } | Command::Events { .. } | Command::Wait { .. } | Command::On { .. }) => {
+            unreachable!("handled before enumerating")
+        }
+    }
+}
+
+/// Parse the filter expression from the settings, pointing at any syntax error
+fn parse_filter(settings: &Settings) -> Result<Option<Filter>> {
+    settings
+        .filter
+        .as_deref()
+        .map(|expr| {
+            expr.parse::<Filter>()
+                .map_err(|e| anyhow!("invalid filter expression:\n{}", e.annotate(expr)))
+        })
+        .transpose()
+}
+
+/// Print the whole tree or list, applying VID:PID and expression filters
+fn print_tree(
+    tree: &UsbTree<UsbDevice>,
+    settings: &Settings,
+    labels: &Labels,
+    ports: &PortMap,
+) -> Result<()> {
+    let vid_pid = VidPidFilter::parse_list(settings.vid_pid.iter().flatten())?;
+    let filter = parse_filter(settings)?;
+    let matches = |dev: &UsbDevice| {
+        vid_pid.matches(dev) && filter.as_ref().is_none_or(|f| f.matches(dev))
+    };
+    let style = settings.tree_style().context("loading theme")?;
+
+    match settings.format.unwrap_or_default() {
+        OutputFormat::Tree => {
+            let mut formatter = TreeFormatter::with_style(tree, style)
+                .with_labels(labels)
+                .with_ports(ports);
+            if !vid_pid.is_empty() || filter.is_some() {
+                formatter = formatter.with_filter(matches);
+            }
+            print!("{}", formatter);
+        }
+        OutputFormat::List => {
+            let columns = settings
+                .columns
+                .clone()
+                .unwrap_or_else(|| Column::DEFAULT.to_vec());
+            let formatter = ListFormatter::new(tree)
+                .with_columns(columns)
+                .with_header(style.show_header)
+                .with_filter(matches);
+            print!("{}", formatter);
+        }
+    }
+    Ok(())
+}
+
+/// Build the VID:PID and expression filter from the settings
+fn device_filter(settings: &Settings) -> Result<impl Fn(&UsbDevice) -> bool + Send + use<>> {
+    let vid_pid = VidPidFilter::parse_list(settings.vid_pid.iter().flatten())?;
+    let filter = parse_filter(settings)?;
+    Ok(move |dev: &UsbDevice| {
+        vid_pid.matches(dev) && filter.as_ref().is_none_or(|f| f.matches(dev))
+    })
+}
+
+/// Follow hotplug events until a callback breaks, the timeout expires,
+/// one of the `interrupt` signals arrives or the source runs out
+///
+/// `on_start` gets the tree as it was when watching started and when that
+/// was, `on_change` every change to a device matching the filters, along
+/// with the updated tree and when the change happened. During a replay
+/// the timeout counts recorded time, so it scales with the replay speed.
+fn follow(
+    settings: &Settings,
+    mut source: Source,
+    timeout: Option<Duration>,
+    interrupt: Option<&mut Signals>,
+    on_start: impl FnOnce(&UsbTree<UsbDevice>, SystemTime) -> Result<ControlFlow<()>>,
+    on_change: impl FnMut(&TreeChange, &UsbTree<UsbDevice>, SystemTime) -> Result<ControlFlow<()>>,
+) -> Result<Followed> {
+    let followed = follow_source(settings, &mut source, timeout, interrupt, on_start, on_change);
+    // Finish the recording however following ended, so a failed write is
+    // reported rather than lost
+    let finished = source.finish();
+    let followed = followed?;
+    finished?;
+    Ok(followed)
+}
+
+/// The loop of [`follow`], leaving the source to be finished by it
+fn follow_source(
+    settings: &Settings,
+    source: &mut Source,
+    timeout: Option<Duration>,
+    mut interrupt: Option<&mut Signals>,
+    on_start: impl FnOnce(&UsbTree<UsbDevice>, SystemTime) -> Result<ControlFlow<()>>,
+    mut on_change: impl FnMut(&TreeChange, &UsbTree<UsbDevice>, SystemTime) -> Result<ControlFlow<()>>,
+) -> Result<Followed> {
+    let matches = device_filter(settings)?;
+    let live = LiveTree::new();
+    live.sync(block_on(source.snapshot())?);
+    if on_start(&live.read(), source.timestamp())?.is_break() {
+        return Ok(Followed::Stopped);
+    }
+
+    // Sleep on the blocking pool, so the deadline can race the next event
+    let started = source.timestamp();
+    let speed = source.speed();
+    let mut deadline = timeout.filter(|_| speed.is_finite()).map(|timeout| {
+        let timeout = timeout.div_f64(speed);
+        blocking::unblock(move || thread::sleep(timeout))
+    });
+    loop {
+        let next = async { Ok(source.next_event().await) };
+        let timed_out = async {
+            match deadline {
+                Some(ref mut deadline) => deadline.await,
+                None => future::pending().await,
+            }
+            Err(Followed::TimedOut)
+        };
+        let interrupted = async {
+            match interrupt {
+                Some(ref mut signals) => drop(signals.next().await),
+                None => future::pending().await,
+            }
+            Err(Followed::Interrupted)
+        };
+        let event = match block_on(future::or(next, future::or(timed_out, interrupted))) {
+            Ok(Some(event)) => event,
+            Ok(None) => return Ok(Followed::Ended),
+            Err(followed) => return Ok(followed),
+        };
+        let time = source.timestamp();
+        if timeout.is_some_and(|timeout| time.duration_since(started).unwrap_or_default() > timeout) {
+            return Ok(Followed::TimedOut);
+        }
+        for change in live.apply(event).into_iter().filter_map(|c| c.filter(&matches)) {
+            if on_change(&change, &live.read(), time)?.is_break() {
+                return Ok(Followed::Stopped);
+            }
+        }
+    }
+}
+
+/// Print the current tree, then a line for every change until Ctrl-C or
+/// until `duration` is over
+///
+/// Ports and devices that start flapping are flagged as they do, and the
+/// reconnect summary is printed once watching ends, Ctrl-C included. The
+/// report file, if any, is rewritten on every change so it also survives
+/// the process being killed.
+fn watch(
+    settings: &Settings,
+    source: Source,
+    labels: &Labels,
+    ports: &PortMap,
+    duration: Option<Duration>,
+    threshold: FlapThreshold,
+    report: Option<&Path>,
+) -> Result<()> {
+    // Both callbacks update the tracker
+    let tracker = RefCell::new(FlapTracker::new(threshold));
+    let write_report = |tracker: &FlapTracker| -> Result<()> {
+        if let Some(path) = report {
+            let json = serde_json::to_string_pretty(&tracker.report())?;
+            fs::write(path, json + "\n")
+                .with_context(|| format!("writing report '{}'", path.display()))?;
+        }
+        Ok(())
+    };
+
+    let on_start = |tree: &UsbTree<UsbDevice>, time| {
+        print_tree(tree, settings, labels, ports)?;
+        println!();
+        tracker.borrow_mut().start(tree, time);
+        write_report(&tracker.borrow())?;
+        Ok(ControlFlow::Continue(()))
+    };
+    let mut signals = Signals::new([Signal::Int]).context("catching Ctrl-C")?;
+    let interrupt = Some(&mut signals);
+    let followed = follow(settings, source, duration, interrupt, on_start, |change, _, time| {
+        println!("{}", describe_change(change, labels));
+        for record in EventRecord::from_change(change, time) {
+            for target in tracker.borrow_mut().record(&record) {
+                let flag = format!(
+                    "! {} is flapping: {} disconnects within {}",
+                    target,
+                    threshold.disconnects,
+                    humantime::format_duration(threshold.window)
+                );
+                println!("{}", flag.red().bold());
+            }
+        }
+        write_report(&tracker.borrow())?;
+        Ok(ControlFlow::Continue(()))
+    })?;
+
+    if let Followed::TimedOut | Followed::Ended | Followed::Interrupted = followed {
+        let summary = tracker.borrow().report();
+        println!();
+        print!("{}", summary);
+        if duration.is_some() && summary.any_flapping() {
+            process::exit(EXIT_FLAPPING);
+        }
+    }
     Ok(())
 }
+
+/// Print every attach and detach as text or JSON Lines until interrupted
+fn events(settings: &Settings, source: Source, labels: &Labels, format: EventFormat) -> Result<()> {
+    let on_change = |change: &TreeChange, _: &UsbTree<UsbDevice>, time| {
+        match format {
+            EventFormat::Text => println!("{}", describe_change(change, labels)),
+            EventFormat::Jsonl => {
+                for record in EventRecord::from_change(change, time) {
+                    println!("{}", serde_json::to_string(&record)?);
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    };
+    let on_start = |_: &UsbTree<UsbDevice>, _| Ok(ControlFlow::Continue(()));
+    follow(settings, source, None, None, on_start, on_change).map(drop)
+}
+
+/// Wait for a matching device to appear, or with `gone` for none to be left
+///
+/// Prints the path of the device that appeared, or of those that went
+/// away, and exits with [`EXIT_TIMEOUT`] if the timeout expires first.
+fn wait(
+    settings: &Settings,
+    mut source: Source,
+    labels: &Labels,
+    serial: Option<&str>,
+    location: Option<&DeviceLocation>,
+    gone: bool,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    if let Some(location) = location {
+        location.validate(labels)?;
+    }
+    let filter = device_filter(settings)?;
+    let wanted = |path: &DevicePath, dev: &UsbDevice| {
+        filter(dev)
+            && serial.is_none_or(|serial| dev.serial.as_deref() == Some(serial))
+            && location.is_none_or(|location| location.matches(path, dev, labels))
+    };
+    let device_wait = if gone { DeviceWait::gone(wanted) } else { DeviceWait::appear(wanted) };
+    let outcome = block_on(device_wait.run(&mut source, timeout));
+    source.finish()?;
+    match outcome? {
+        WaitOutcome::Met(paths) => {
+            for path in paths {
+                println!("{}", path);
+            }
+            Ok(())
+        }
+        WaitOutcome::Ended => bail!("event stream ended before the wait was over"),
+        WaitOutcome::TimedOut => {
+            eprintln!(
+                "timed out after {} waiting for {}",
+                humantime::format_duration(timeout.unwrap_or_default()),
+                if gone { "matching devices to go away" } else { "a matching device" }
+            );
+            process::exit(EXIT_TIMEOUT);
+        }
+    }
+}
+
+/// Run hooks for matching attach and detach events until interrupted
+fn on(settings: &Settings, source: Source, hooks: Vec<Hook>, labels: &Labels) -> Result<()> {
+    if hooks.is_empty() {
+        bail!("no command given after -- and no [[hook]] in the config file");
+    }
+    let hooks = hooks
+        .into_iter()
+        .map(|hook| Ok((hook_filter(&hook)?, hook)))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Hooks run on their own thread, so slow commands do not hold up events
+    let (sender, receiver) = mpsc::channel();
+    let runner = thread::spawn(move || run_hooks(&hooks, receiver));
+    let on_start = |_: &UsbTree<UsbDevice>, _| Ok(ControlFlow::Continue(()));
+    let followed = follow(settings, source, None, None, on_start, |change, _, time| {
+        let label = labels.label_for(change.device()).map(str::to_string);
+        for record in EventRecord::from_change(change, time) {
+            sender.send((record, label.clone()))?;
+        }
+        Ok(ControlFlow::Continue(()))
+    });
+
+    // Let pending hooks run before exiting
+    drop(sender);
+    runner.join().map_err(|_| anyhow!("hook runner panicked"))?;
+    followed.map(drop)
+}
+
+/// Build the VID:PID and expression filter of a hook
+fn hook_filter(hook: &Hook) -> Result<impl Fn(&UsbDevice) -> bool + Send + use<>> {
+    device_filter(&Settings {
+        vid_pid: hook.vid_pid.clone(),
+        filter: hook.filter.clone(),
+        ..Default::default()
+    })
+}
+
+/// How often running hook commands are checked for having exited
+const HOOK_POLL: Duration = Duration::from_millis(50);
+
+/// Start the matching hooks for each received event once its path has
+/// been quiet for the hook's debounce time
+fn run_hooks<F: Fn(&UsbDevice) -> bool>(
+    hooks: &[(F, Hook)],
+    records: mpsc::Receiver<(EventRecord, Option<String>)>,
+) {
+    let mut debouncer = Debouncer::new();
+    let mut processes = HookProcesses::default();
+    loop {
+        // Wake up for the next debounce deadline, and regularly to reap
+        let poll = (!processes.is_empty()).then(|| Instant::now() + HOOK_POLL);
+        let received = match debouncer.next_due().into_iter().chain(poll).min() {
+            Some(due) => records.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => records.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok((record, label)) => {
+                for (i, (matches, hook)) in hooks.iter().enumerate() {
+                    if hook.triggers_on(record.kind) && matches(&record.device) {
+                        let key = (i, record.path.clone());
+                        let due = Instant::now() + hook.debounce;
+                        debouncer.push(key, (i, record.clone(), label.clone()), due);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                for (i, record, label) in debouncer.drain() {
+                    processes.start(&hooks[i].1, &record, label.as_deref());
+                }
+                processes.wait_all();
+                return;
+            }
+        }
+        for (i, record, label) in debouncer.ready(Instant::now()) {
+            processes.start(&hooks[i].1, &record, label.as_deref());
+        }
+        processes.reap();
+    }
+}
+
+/// A hook command with a name for error messages
+struct HookCommand {
+    name: String,
+    path: DevicePath,
+    command: process::Command,
+}
+
+/// Hook commands running in the background
+///
+/// Hooks for different paths run side by side, so a slow hook for one
+/// device does not hold up the others. Hooks for the same path run one
+/// after another, in the order they became due.
+#[derive(Default)]
+struct HookProcesses {
+    running: BTreeMap<DevicePath, (HookCommand, process::Child)>,
+    queued: BTreeMap<DevicePath, VecDeque<HookCommand>>,
+}
+
+impl HookProcesses {
+    /// Start a hook, or queue it behind the one running for its path
+    fn start(&mut self, hook: &Hook, record: &EventRecord, label: Option<&str>) {
+        let Some(command) = hook.command(record, label) else {
+            return;
+        };
+        let command = HookCommand {
+            name: hook.run.join(" "),
+            path: record.path.clone(),
+            command,
+        };
+        if self.running.contains_key(&command.path) {
+            self.queued.entry(command.path.clone()).or_default().push_back(command);
+        } else {
+            self.spawn(command);
+        }
+    }
+
+    fn spawn(&mut self, mut hook: HookCommand) {
+        match hook.command.spawn() {
+            Ok(child) => {
+                self.running.insert(hook.path.clone(), (hook, child));
+            }
+            Err(e) => {
+                eprintln!("failed to run hook '{}': {}", hook.name, e);
+                self.start_next(&hook.path);
+            }
+        }
+    }
+
+    fn start_next(&mut self, path: &DevicePath) {
+        if let Some(next) = self.queued.get_mut(path).and_then(VecDeque::pop_front) {
+            self.spawn(next);
+        }
+    }
+
+    /// Collect hooks that exited, reporting failures, and start queued ones
+    fn reap(&mut self) {
+        let mut exited = Vec::new();
+        for (path, (hook, child)) in &mut self.running {
+            match child.try_wait() {
+                Ok(Some(status)) if !status.success() => {
+                    eprintln!("hook '{}' failed for {}: {}", hook.name, path, status)
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => continue,
+                Err(e) => eprintln!("failed to wait for hook '{}': {}", hook.name, e),
+            }
+            exited.push(path.clone());
+        }
+        for path in exited {
+            self.running.remove(&path);
+            self.start_next(&path);
+        }
+    }
+
+    /// Wait until every running and queued hook has finished
+    fn wait_all(&mut self) {
+        while !self.is_empty() {
+            self.reap();
+            thread::sleep(HOOK_POLL);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+}
+
+/// Format a tree change as a `+`, `-` or `~` line
+fn describe_change(change: &TreeChange, labels: &Labels) -> String {
+    let device = change.device();
+    let mut line = match change {
+        TreeChange::Added { .. } => format!("+ {} {}", change.path(), device).green(),
+        TreeChange::Removed { .. } => format!("- {} {}", change.path(), device).red(),
+        TreeChange::Changed { .. } => format!("~ {} {}", change.path(), device).yellow(),
+    }
+    .to_string();
+    if let Some(label) = labels.label_for(device) {
+        line.push_str(&format!(" [{}]", label));
+    }
+    line
+}
+
+/// Print one device's details followed by its subtree
+fn show(
+    tree: &UsbTree<UsbDevice>,
+    path: &DevicePath,
+    settings: &Settings,
+    labels: &Labels,
+    ports: &PortMap,
+) -> Result<()> {
+    let device = tree.try_get_by_path(path)?;
+    print_device(device, labels, ports);
+
+    if tree.get_subtree_by_path(path).len() > 1 {
+        let style = settings.tree_style().context("loading theme")?;
+        println!();
+        print!(
+            "{}",
+            TreeFormatter::with_style(tree, style)
+                .with_labels(labels)
+                .with_ports(ports)
+                .with_root(path.clone())
+        );
+    }
+    Ok(())
+}
+
+/// Print the physical port a path is plugged into
+fn locate(path: &DevicePath, ports: &PortMap) -> Result<()> {
+    let (port, name) = ports
+        .physical_port(path)
+        .ok_or_else(|| anyhow!("no named port for {}", path))?;
+    if port == *path {
+        println!("{}: {}", path, name);
+    } else {
+        let hubs = path.depth() - port.depth();
+        let plural = if hubs == 1 { "" } else { "s" };
+        println!("{}: {} (port {}, {} hub{} deep)", path, name, port, hubs, plural);
+    }
+    Ok(())
+}
+
+/// Print how the tree compares to a manifest, exiting with [`EXIT_DRIFT`] on drift
+fn check(tree: &UsbTree<UsbDevice>, manifest: &Path, labels: &Labels) -> Result<()> {
+    let manifest = Manifest::from_file(manifest)?;
+    let report = manifest.check(tree, labels)?;
+    print!("{}", report);
+    if !report.passed() {
+        process::exit(EXIT_DRIFT);
+    }
+    Ok(())
+}
+
+/// Print the details of a single device
+fn print_device(device: &UsbDevice, labels: &Labels, ports: &PortMap) {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+
+    println!("Path:          {}", device.path());
+    if let Some(label) = labels.label_for(device) {
+        println!("Label:         {}", label);
+    }
+    if let Some((port, name)) = ports.physical_port(&device.path()) {
+        println!("Port:          {} ({})", name, port);
+    }
+    println!("ID:            {}", device.vid_pid());
+    println!("Bus/Address:   {:03}/{:03}", device.bus, device.address);
+    println!("Sysfs:         {}", device.path().to_sysfs_name());
+    if let Some(id_path) = UdevIdPath::for_path(&device.path()) {
+        println!("ID_PATH:       {}", id_path);
+    }
+    println!("Manufacturer:  {}", optional(&device.manufacturer));
+    println!("Product:       {}", optional(&device.product));
+    println!("Serial:        {}", optional(&device.serial));
+    println!(
+        "Class:         {} (0x{:02x})",
+        class_name(device.class).unwrap_or("unknown"),
+        device.class
+    );
+    println!(
+        "Speed:         {}",
+        device.speed.map(speed_name).unwrap_or("-")
+    );
+    let number = |value: Option<u8>| value.map_or_else(|| "-".to_string(), |v| v.to_string());
+    println!("USB version:   {}", device.usb_version());
+    println!("Firmware:      {}", device.firmware_version());
+    println!("Max packet 0:  {}", number(device.max_packet_size_0));
+    println!("Configs:       {}", number(device.num_configurations));
+    println!("Active config: {}", number(device.active_config));
+    if !device.drivers.is_empty() {
+        println!("Drivers:       {}", device.drivers.join(", "));
+    }
+    if let Some(ref path) = device.sysfs_path {
+        println!("Sysfs path:    {}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_options_need_a_command() {
+        assert!(Cli::try_parse_from(["usbinfo", "on"]).is_ok());
+        assert!(Cli::try_parse_from(["usbinfo", "--vid-pid", "0403:*", "on"]).is_ok());
+        assert!(Cli::try_parse_from(["usbinfo", "on", "--attach", "--", "true"]).is_ok());
+        for option in [&["--attach"][..], &["--detach"], &["--debounce", "1s"]] {
+            let args = ["usbinfo", "on"].iter().chain(option);
+            assert!(Cli::try_parse_from(args).is_err(), "{:?}", option);
+        }
+    }
+}
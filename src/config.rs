@@ -0,0 +1,324 @@
+//! User configuration file and named profiles
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::error::{ConfigError, LabelError, PortMapError, ThemeError};
+use crate::formatter::{Column, OutputFormat, TreeStyle};
+use crate::hooks::Hook;
+use crate::labels::Labels;
+use crate::ports::PortMap;
+use crate::theme::{ColorMode, Theme};
+
+/// Environment variable overriding the config file location
+pub const CONFIG_ENV: &str = "USBINFO_CONFIG";
+
+/// Output settings shared by the `[defaults]` section and each profile
+///
+/// Every field is optional so that settings can be layered: defaults,
+/// then the selected profile, then command-line flags.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Use ASCII connectors instead of Unicode box drawing
+    pub ascii: Option<bool>,
+    /// When to use colors
+    pub color: Option<ColorMode>,
+    /// Path to a theme file
+    pub theme: Option<PathBuf>,
+    /// Whether to print headers: bus lines in trees, column names in lists
+    pub header: Option<bool>,
+    /// Output format
+    pub format: Option<OutputFormat>,
+    /// VID:PID patterns, see [`VidPidPattern`](crate::VidPidPattern)
+    pub vid_pid: Option<Vec<String>>,
+    /// Filter expression, see [`Filter`](crate::Filter)
+    #[serde(rename = "where")]
+    pub filter: Option<String>,
+    /// Columns for list output
+    pub columns: Option<Vec<Column>>,
+    /// Path to a labels file
+    pub labels: Option<PathBuf>,
+    /// Path to a physical port map file
+    pub ports: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Overlay `other` on top of these settings; fields set in `other` win
+    pub fn merge(self, other: Settings) -> Settings {
+        Settings {
+            ascii: other.ascii.or(self.ascii),
+            color: other.color.or(self.color),
+            theme: other.theme.or(self.theme),
+            header: other.header.or(self.header),
+            format: other.format.or(self.format),
+            vid_pid: other.vid_pid.or(self.vid_pid),
+            filter: other.filter.or(self.filter),
+            columns: other.columns.or(self.columns),
+            labels: other.labels.or(self.labels),
+            ports: other.ports.or(self.ports),
+        }
+    }
+
+    /// Build the tree style described by these settings
+    pub fn tree_style(&self) -> Result<TreeStyle, ThemeError> {
+        let mut style = if self.ascii.unwrap_or(false) {
+            TreeStyle::ascii()
+        } else {
+            TreeStyle::default()
+        };
+        style = style
+            .with_color_mode(self.color.unwrap_or_default())
+            .with_header(self.header.unwrap_or(true));
+        if let Some(ref path) = self.theme {
+            style = style.with_theme(Theme::from_file(expand_home(path))?);
+        }
+        Ok(style)
+    }
+
+    /// Load the labels file named by these settings, or the default one
+    pub fn labels(&self) -> Result<Labels, LabelError> {
+        match self.labels {
+            Some(ref path) => Labels::from_file(expand_home(path)),
+            None => Labels::load(),
+        }
+    }
+
+    /// Load the port map named by these settings, or the default one
+    pub fn port_map(&self) -> Result<PortMap, PortMapError> {
+        match self.ports {
+            Some(ref path) => PortMap::from_file(expand_home(path)),
+            None => PortMap::load(),
+        }
+    }
+
+    /// Resolve relative file paths against `dir`, leaving `~/` paths alone
+    fn resolve_paths(&mut self, dir: &Path) {
+        for path in [&mut self.theme, &mut self.labels, &mut self.ports].into_iter().flatten() {
+            if path.is_relative() && !path.starts_with("~") {
+                *path = dir.join(&*path);
+            }
+        }
+    }
+}
+
+/// Parsed configuration file
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{Config, OutputFormat};
+///
+/// let config: Config = r#"
+///     [defaults]
+///     ascii = true
+///
+///     [profile.lab]
+///     format = "list"
+///     vid_pid = ["0483:5740"]
+///     columns = ["path", "vidpid", "serial"]
+/// "#.parse().unwrap();
+///
+/// let settings = config.settings(Some("lab")).unwrap();
+/// assert_eq!(settings.ascii, Some(true));
+/// assert_eq!(settings.format, Some(OutputFormat::List));
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings applied to every invocation
+    pub defaults: Settings,
+    /// Named profiles selected with `-P NAME`
+    #[serde(rename = "profile")]
+    pub profiles: HashMap<String, Settings>,
+    /// Commands run by `usbinfo on` when devices attach or detach
+    #[serde(rename = "hook")]
+    pub hooks: Vec<Hook>,
+}
+
+impl Config {
+    /// Location of the config file
+    ///
+    /// `$USBINFO_CONFIG` if set, otherwise `config.toml` in [`config_dir`].
+    pub fn default_path() -> Option<PathBuf> {
+        match env::var_os(CONFIG_ENV) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => config_dir().map(|dir| dir.join("config.toml")),
+        }
+    }
+
+    /// Load the config from its default location
+    ///
+    /// A missing file at the standard location yields an empty config, but a
+    /// missing file named by `$USBINFO_CONFIG` is an error.
+    pub fn load() -> Result<Self, ConfigError> {
+        let Some(path) = Self::default_path() else {
+            return Ok(Self::default());
+        };
+        match Self::from_file(&path) {
+            Err(ConfigError::Io { ref source, .. })
+                if source.kind() == io::ErrorKind::NotFound && env::var_os(CONFIG_ENV).is_none() =>
+            {
+                Ok(Self::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Load the config from a TOML file
+    ///
+    /// Relative `theme`, `labels` and `ports` paths in the file are taken
+    /// relative to the file's directory, so a shared config works from
+    /// anywhere.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut config: Config = fs::read_to_string(path)
+            .map_err(|source| ConfigError::Io {
+                path: path.to_path_buf(),
+                source,
+            })?
+            .parse()?;
+        if let Some(dir) = path.parent() {
+            config.defaults.resolve_paths(dir);
+            for settings in config.profiles.values_mut() {
+                settings.resolve_paths(dir);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Resolve the effective settings for an optional profile
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings, ConfigError> {
+        let defaults = self.defaults.clone();
+        match profile {
+            None => Ok(defaults),
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(|p| defaults.merge(p.clone()))
+                .ok_or_else(|| ConfigError::UnknownProfile(name.to_string())),
+        }
+    }
+
+    /// Names of all defined profiles, sorted
+    pub fn profile_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.profiles.keys().map(|s| s.as_str()).collect();
+        names.sort();
+        names
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: Config = toml::from_str(s)?;
+        if let Some(i) = config.hooks.iter().position(|hook| hook.run.is_empty()) {
+            return Err(ConfigError::EmptyHook(i + 1));
+        }
+        Ok(config)
+    }
+}
+
+/// Directory holding usbinfo's config files
+///
+/// `$XDG_CONFIG_HOME/usbinfo`, falling back to `~/.config/usbinfo`.
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("usbinfo"))
+}
+
+/// Expand a leading `~/` to the user's home directory
+pub(crate) fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_overrides_defaults() {
+        let config: Config = r#"
+            [defaults]
+            format = "tree"
+            color = "never"
+
+            [profile.lab]
+            format = "list"
+        "#
+        .parse()
+        .unwrap();
+
+        let settings = config.settings(Some("lab")).unwrap();
+        assert_eq!(settings.format, Some(OutputFormat::List));
+        assert_eq!(settings.color, Some(ColorMode::Never));
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let config = Config::default();
+        assert!(matches!(
+            config.settings(Some("lab")),
+            Err(ConfigError::UnknownProfile(name)) if name == "lab"
+        ));
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys() {
+        assert!("[defaults]\nfromat = \"list\"".parse::<Config>().is_err());
+        assert!("[defaults]\ncolumns = [\"bogus\"]".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_rejects_hook_without_command() {
+        let err = "[[hook]]\non = \"attach\"".parse::<Config>().unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyHook(1)));
+    }
+
+    #[test]
+    fn test_paths_relative_to_config() {
+        let dir = std::env::temp_dir().join(format!("usbinfo-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(
+            &path,
+            "[defaults]\ntheme = \"theme.toml\"\nlabels = \"~/labels.toml\"\n\n[profile.lab]\nports = \"lab/ports.toml\"\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.defaults.theme, Some(dir.join("theme.toml")));
+        assert_eq!(config.defaults.labels, Some(PathBuf::from("~/labels.toml")));
+        assert_eq!(config.profiles["lab"].ports, Some(dir.join("lab/ports.toml")));
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = Settings {
+            ascii: Some(true),
+            header: Some(false),
+            ..Settings::default()
+        };
+        let over = Settings {
+            header: Some(true),
+            ..Settings::default()
+        };
+        let merged = base.merge(over);
+        assert_eq!(merged.ascii, Some(true));
+        assert_eq!(merged.header, Some(true));
+    }
+}
@@ -1,12 +1,16 @@
 //! USB device representation
 
 use std::fmt;
-use nusb::DeviceInfo;
+use std::fs;
+use std::path::{Path, PathBuf};
+use nusb::{DeviceInfo, Speed};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::path::DevicePath;
 
 /// Represents a USB device
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UsbDevice {
     /// Vendor ID
     pub vid: u16,
@@ -30,15 +34,37 @@ pub struct UsbDevice {
     pub subclass: u8,
     /// Device protocol
     pub protocol: u8,
+    /// Interface classes (bInterfaceClass), in interface order
+    pub interface_classes: Vec<u8>,
     /// USB speed
+    #[serde(serialize_with = "serialize_speed", deserialize_with = "deserialize_speed")]
     pub speed: Option<nusb::Speed>,
     /// Port path (for building hierarchy)
     pub port_path: Vec<u8>,
+    /// USB specification release (bcdUSB), e.g. 0x0210 for USB 2.1
+    pub bcd_usb: u16,
+    /// Device release number (bcdDevice), usually the firmware revision
+    pub bcd_device: u16,
+    /// Maximum packet size for endpoint zero (bMaxPacketSize0)
+    pub max_packet_size_0: Option<u8>,
+    /// Number of configurations (bNumConfigurations)
+    pub num_configurations: Option<u8>,
+    /// Active configuration value, `None` if unconfigured or unknown
+    pub active_config: Option<u8>,
+    /// Device directory in sysfs (Linux only)
+    pub sysfs_path: Option<PathBuf>,
+    /// Kernel drivers bound to the device's interfaces, sorted (Linux only)
+    pub drivers: Vec<String>,
 }
 
 impl UsbDevice {
     /// Create a UsbDevice from nusb DeviceInfo
     pub fn from_device_info(info: &DeviceInfo) -> Self {
+        Self::from_nusb(info).with_sysfs(sysfs_path(info))
+    }
+
+    /// Create a UsbDevice from what nusb reports, without reading sysfs
+    pub(crate) fn from_nusb(info: &DeviceInfo) -> Self {
         Self {
             vid: info.vendor_id(),
             pid: info.product_id(),
@@ -51,11 +77,37 @@ impl UsbDevice {
             class: info.class(),
             subclass: info.subclass(),
             protocol: info.protocol(),
+            interface_classes: info.interfaces().map(|i| i.class()).collect(),
             speed: info.speed(),
             port_path: info.port_chain().to_vec(),
+            bcd_usb: info.usb_version(),
+            bcd_device: info.device_version(),
+            ..Self::default()
         }
     }
 
+    /// Fill in the descriptor fields nusb does not report from a sysfs device directory
+    pub fn with_sysfs(mut self, dir: Option<PathBuf>) -> Self {
+        if let Some(ref dir) = dir {
+            self.max_packet_size_0 = read_sysfs_number(dir, "bMaxPacketSize0");
+            self.num_configurations = read_sysfs_number(dir, "bNumConfigurations");
+            self.active_config = read_sysfs_number(dir, "bConfigurationValue");
+            self.drivers = read_interface_drivers(dir);
+        }
+        self.sysfs_path = dir;
+        self
+    }
+
+    /// Firmware revision (bcdDevice) as "major.minor", e.g. "1.20"
+    pub fn firmware_version(&self) -> String {
+        format_bcd(self.bcd_device)
+    }
+
+    /// USB specification release (bcdUSB) as "major.minor", e.g. "2.10"
+    pub fn usb_version(&self) -> String {
+        format_bcd(self.bcd_usb)
+    }
+
     /// Returns the VID:PID string (e.g., "1234:5678")
     pub fn vid_pid(&self) -> String {
         format!("{:04x}:{:04x}", self.vid, self.pid)
@@ -66,6 +118,17 @@ impl UsbDevice {
         self.class == 9
     }
 
+    /// Class describing what the device does
+    ///
+    /// Most devices declare class 0 and leave the class to each interface,
+    /// so for those this is the class of the first interface.
+    pub fn function_class(&self) -> u8 {
+        match (self.class, self.interface_classes.first()) {
+            (0, Some(&class)) => class,
+            (class, _) => class,
+        }
+    }
+
     /// Get the DevicePath for this device
     pub fn path(&self) -> DevicePath {
         DevicePath::new(self.bus, self.port_path.clone())
@@ -106,6 +169,15 @@ impl From<UsbDevice> for DevicePath {
     }
 }
 
+/// Parse a hexadecimal VID:PID pair such as "0483:5740"
+pub fn parse_vid_pid(s: &str) -> Option<(u16, u16)> {
+    let (vid, pid) = s.split_once(':')?;
+    Some((
+        u16::from_str_radix(vid, 16).ok()?,
+        u16::from_str_radix(pid, 16).ok()?,
+    ))
+}
+
 /// Filter predicate for VID:PID pairs
 pub fn matches_vid_pid(device: &UsbDevice, filters: &[(u16, u16)]) -> bool {
     if filters.is_empty() {
@@ -115,3 +187,162 @@ pub fn matches_vid_pid(device: &UsbDevice, filters: &[(u16, u16)]) -> bool {
         .iter()
         .any(|(vid, pid)| device.vid == *vid && device.pid == *pid)
 }
+
+/// Well-known USB class codes and their short names
+const CLASS_NAMES: &[(u8, &str)] = &[
+    (0x00, "interface"),
+    (0x01, "audio"),
+    (0x02, "cdc"),
+    (0x03, "hid"),
+    (0x05, "physical"),
+    (0x06, "image"),
+    (0x07, "printer"),
+    (0x08, "storage"),
+    (0x09, "hub"),
+    (0x0a, "cdc-data"),
+    (0x0b, "smart-card"),
+    (0x0d, "content-security"),
+    (0x0e, "video"),
+    (0x0f, "healthcare"),
+    (0x10, "audio-video"),
+    (0x11, "billboard"),
+    (0xdc, "diagnostic"),
+    (0xe0, "wireless"),
+    (0xef, "misc"),
+    (0xfe, "application"),
+    (0xff, "vendor"),
+];
+
+/// Get the short name of a USB class code (e.g., "hub" for 0x09)
+pub fn class_name(class: u8) -> Option<&'static str> {
+    CLASS_NAMES
+        .iter()
+        .find(|(code, _)| *code == class)
+        .map(|(_, name)| *name)
+}
+
+/// Parse a USB class from a name ("hid") or number ("3", "0x03")
+pub(crate) fn parse_class(s: &str) -> Option<u8> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(&(code, _)) = CLASS_NAMES.iter().find(|(_, name)| *name == lower) {
+        return Some(code);
+    }
+    match lower.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => lower.parse().ok(),
+    }
+}
+
+/// Get a device's sysfs directory
+#[cfg(target_os = "linux")]
+pub(crate) fn sysfs_path(info: &DeviceInfo) -> Option<PathBuf> {
+    Some(info.sysfs_path().to_path_buf())
+}
+
+/// Get a device's sysfs directory
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn sysfs_path(_info: &DeviceInfo) -> Option<PathBuf> {
+    None
+}
+
+/// Read a numeric sysfs attribute, returning `None` if missing or empty
+fn read_sysfs_number(dir: &Path, attribute: &str) -> Option<u8> {
+    fs::read_to_string(dir.join(attribute)).ok()?.trim().parse().ok()
+}
+
+/// Read the drivers bound to the interfaces of a sysfs device directory
+///
+/// Interfaces are the subdirectories named like `1-2.3:1.0`; each bound
+/// one has a `driver` symlink to its driver's directory.
+fn read_interface_drivers(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut drivers: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().contains(':'))
+        .filter_map(|entry| fs::read_link(entry.path().join("driver")).ok())
+        .filter_map(|driver| Some(driver.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+    drivers.sort();
+    drivers.dedup();
+    drivers
+}
+
+/// Format a binary-coded decimal version such as 0x0120 as "1.20"
+pub(crate) fn format_bcd(bcd: u16) -> String {
+    format!("{:x}.{:02x}", bcd >> 8, bcd & 0xff)
+}
+
+/// Parse a version such as "1.20" or "0x0120" into binary-coded decimal
+///
+/// A single minor digit is the tens digit, so "1.2" is the same as "1.20".
+pub(crate) fn parse_bcd(s: &str) -> Option<u16> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    let (major, minor) = s.split_once('.').unwrap_or((s, "0"));
+    let digits = |d: &str| !d.is_empty() && d.len() <= 2 && d.bytes().all(|b| b.is_ascii_digit());
+    if !digits(major) || !digits(minor) {
+        return None;
+    }
+    let minor = format!("{:0<2}", minor);
+    Some((u16::from_str_radix(major, 16).ok()? << 8) | u16::from_str_radix(&minor, 16).ok()?)
+}
+
+/// Get the short name of a USB speed (e.g., "high")
+pub fn speed_name(speed: Speed) -> &'static str {
+    match speed {
+        Speed::Low => "low",
+        Speed::Full => "full",
+        Speed::High => "high",
+        Speed::Super => "super",
+        Speed::SuperPlus => "super+",
+        _ => "unknown",
+    }
+}
+
+/// Serialize a speed by its short name, or as none if it has no name
+fn serialize_speed<S: Serializer>(speed: &Option<Speed>, serializer: S) -> Result<S::Ok, S::Error> {
+    speed.and_then(|s| parse_speed(speed_name(s))).map(speed_name).serialize(serializer)
+}
+
+/// Deserialize a speed from its short name or Mbit/s value
+pub(crate) fn deserialize_speed<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Speed>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| {
+            parse_speed(&s).ok_or_else(|| serde::de::Error::custom(format!("unknown speed: '{}'", s)))
+        })
+        .transpose()
+}
+
+/// Parse a USB speed from its short name or Mbit/s value
+pub(crate) fn parse_speed(s: &str) -> Option<Speed> {
+    match s.to_ascii_lowercase().as_str() {
+        "low" | "1.5" => Some(Speed::Low),
+        "full" | "12" => Some(Speed::Full),
+        "high" | "480" => Some(Speed::High),
+        "super" | "5000" => Some(Speed::Super),
+        "super+" | "superplus" | "10000" => Some(Speed::SuperPlus),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bcd_versions() {
+        assert_eq!(format_bcd(0x0120), "1.20");
+        assert_eq!(format_bcd(0x1005), "10.05");
+        assert_eq!(parse_bcd("1.20"), Some(0x0120));
+        assert_eq!(parse_bcd("1.2"), Some(0x0120));
+        assert_eq!(parse_bcd("2"), Some(0x0200));
+        assert_eq!(parse_bcd("0x0311"), Some(0x0311));
+        assert_eq!(parse_bcd("1.234"), None);
+        assert_eq!(parse_bcd("1.a0"), None);
+    }
+}
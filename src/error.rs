@@ -17,6 +17,12 @@ pub enum DevicePathError {
     /// Invalid format (missing colon separator)
     #[error("invalid format, expected 'bus:port.path'")]
     InvalidFormat,
+    /// Not a recognized path notation
+    #[error("unrecognized device path notation: '{0}'")]
+    InvalidNotation(String),
+    /// Valid notation, but no connected device matches it
+    #[error("no connected device matches '{0}'")]
+    Unresolved(String),
 }
 
 /// Error type for USB tree operations
@@ -32,3 +38,263 @@ pub enum UsbTreeError {
     #[error("invalid device path: {0}")]
     InvalidPath(#[from] DevicePathError),
 }
+
+/// Error type for loading color themes
+#[derive(Debug, Error)]
+pub enum ThemeError {
+    /// Failed to read the theme file
+    #[error("failed to read theme file: {0}")]
+    Io(#[from] std::io::Error),
+    /// Theme file is not valid TOML
+    #[error("invalid theme file: {0}")]
+    Parse(#[from] toml::de::Error),
+    /// Unknown color name
+    #[error("unknown color: '{0}'")]
+    InvalidColor(String),
+    /// Unknown device class name or number
+    #[error("invalid device class: '{0}'")]
+    InvalidClass(String),
+    /// Unknown USB speed name
+    #[error("invalid speed: '{0}'")]
+    InvalidSpeed(String),
+    /// Invalid vendor ID
+    #[error("invalid vendor ID: '{0}'")]
+    InvalidVendor(String),
+    /// Unknown color mode
+    #[error("invalid color mode: '{0}', expected 'auto', 'always' or 'never'")]
+    InvalidColorMode(String),
+}
+
+/// Error type for output format and column names
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FormatError {
+    /// Unknown output format
+    #[error("unknown output format: '{0}', expected 'tree' or 'list'")]
+    UnknownFormat(String),
+    /// Unknown list column
+    #[error("unknown column: '{0}'")]
+    UnknownColumn(String),
+}
+
+/// Error type for loading the configuration file
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// Failed to read the config file
+    #[error("failed to read config file '{path}': {source}")]
+    Io {
+        /// Path of the config file
+        path: std::path::PathBuf,
+        /// Underlying IO error
+        source: std::io::Error,
+    },
+    /// Config file is not valid TOML or has unknown keys
+    #[error("invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    /// Requested profile is not defined
+    #[error("unknown profile: '{0}'")]
+    UnknownProfile(String),
+    /// A `[[hook]]` entry has no command to run
+    #[error("hook {0} in the config file has no command in `run`")]
+    EmptyHook(usize),
+}
+
+/// Error type for topology manifests
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    /// Failed to read the manifest file
+    #[error("failed to read manifest '{path}': {source}")]
+    Io {
+        /// Path of the manifest
+        path: std::path::PathBuf,
+        /// Underlying IO error
+        source: std::io::Error,
+    },
+    /// Manifest is not valid TOML or has unknown keys
+    #[error("invalid manifest: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// Error type for reading hotplug recordings
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    /// Failed to read the recording
+    #[error("failed to read recording: {0}")]
+    Io(#[from] std::io::Error),
+    /// A line is not a valid recording entry
+    #[error("invalid recording entry on line {line}: {source}")]
+    Parse {
+        /// Line number, starting at 1
+        line: usize,
+        /// Why it failed to parse
+        source: serde_json::Error,
+    },
+    /// The recording does not start with a snapshot
+    #[error("recording does not start with a snapshot")]
+    MissingSnapshot,
+}
+
+/// Error type for device labels
+#[derive(Debug, Error)]
+pub enum LabelError {
+    /// Failed to read the labels file
+    #[error("failed to read labels file: {0}")]
+    Io(#[from] std::io::Error),
+    /// Labels file is not valid TOML
+    #[error("invalid labels file: {0}")]
+    Parse(#[from] toml::de::Error),
+    /// A label entry does not describe a valid target
+    #[error("invalid label '{name}': {reason}")]
+    InvalidEntry {
+        /// Label name
+        name: String,
+        /// What is wrong with the entry
+        reason: String,
+    },
+    /// No label with this name exists
+    #[error("unknown label: '@{0}'")]
+    UnknownLabel(String),
+    /// The labelled device is not connected
+    #[error("device labelled '@{0}' is not connected")]
+    NotConnected(String),
+    /// Path argument is neither a label nor a valid path
+    #[error("invalid device path: {0}")]
+    InvalidPath(#[from] DevicePathError),
+}
+
+/// Error type for physical port maps
+#[derive(Debug, Error)]
+pub enum PortMapError {
+    /// Failed to read the port map file
+    #[error("failed to read port map: {0}")]
+    Io(#[from] std::io::Error),
+    /// Port map file is not valid TOML
+    #[error("invalid port map: {0}")]
+    Parse(#[from] toml::de::Error),
+    /// A port key is not a valid device path
+    #[error("invalid port path '{path}': {source}")]
+    InvalidPath {
+        /// The offending key
+        path: String,
+        /// Why it failed to parse
+        source: DevicePathError,
+    },
+}
+
+/// What went wrong while parsing a filter expression
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FilterErrorKind {
+    /// A character that cannot start a token
+    #[error("unexpected character '{0}'")]
+    UnexpectedChar(char),
+    /// A quoted string without a closing quote
+    #[error("unterminated string")]
+    UnterminatedString,
+    /// A token that does not fit the grammar here
+    #[error("unexpected '{0}'")]
+    UnexpectedToken(String),
+    /// Input ended where an expression was expected
+    #[error("expected an expression")]
+    ExpectedExpression,
+    /// A field name not followed by a comparison operator
+    #[error("expected a comparison operator")]
+    ExpectedOperator,
+    /// Input ended where a value was expected
+    #[error("expected a value")]
+    ExpectedValue,
+    /// An opening parenthesis without a matching closing one
+    #[error("unclosed parenthesis")]
+    UnclosedParen,
+    /// Unknown field name
+    #[error("unknown field '{0}'")]
+    UnknownField(String),
+    /// Operator not supported by the field
+    #[error("operator '{op}' cannot be used with '{field}'")]
+    InvalidOperator {
+        /// Field name
+        field: String,
+        /// Operator
+        op: String,
+    },
+    /// Value not valid for the field
+    #[error("invalid value '{value}' for '{field}'")]
+    InvalidValue {
+        /// Field name
+        field: String,
+        /// Offending value
+        value: String,
+    },
+    /// Malformed regular expression
+    #[error("invalid regular expression: {0}")]
+    InvalidRegex(String),
+}
+
+/// Error type for filter expression parsing, with the position of the problem
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{kind} at column {column}")]
+pub struct FilterError {
+    /// What went wrong
+    pub kind: FilterErrorKind,
+    /// Byte offset into the expression
+    pub position: usize,
+    /// Column of the problem in characters, starting at 1
+    pub column: usize,
+}
+
+impl FilterError {
+    /// Create a new error at the given byte offset
+    ///
+    /// The column assumes one byte per character until
+    /// [`in_source`](Self::in_source) counts it in the expression.
+    pub fn new(kind: FilterErrorKind, position: usize) -> Self {
+        Self {
+            kind,
+            position,
+            column: position + 1,
+        }
+    }
+
+    /// Count the column in characters of the expression the error is in
+    pub fn in_source(self, source: &str) -> Self {
+        let column = self.char_column(source) + 1;
+        Self { column, ..self }
+    }
+
+    /// Render the expression with a caret under the error position
+    pub fn annotate(&self, source: &str) -> String {
+        let column = self.char_column(source);
+        format!("{}\n{}^ {}", source, " ".repeat(column), self.kind)
+    }
+
+    /// Number of characters before the error position
+    fn char_column(&self, source: &str) -> usize {
+        source
+            .get(..self.position)
+            .map_or(self.position, |prefix| prefix.chars().count())
+    }
+}
+
+/// Error type for VID:PID patterns
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum VidPidError {
+    /// Pattern has no ':' separator
+    #[error("missing ':' in '{0}', expected VID:PID")]
+    MissingColon(String),
+    /// VID or PID part is malformed
+    #[error("invalid {part} '{value}' in '{input}': expected a hex ID, '*', a range 'lo-hi' or a mask 'value/mask'")]
+    InvalidId {
+        /// The whole pattern
+        input: String,
+        /// "VID" or "PID"
+        part: &'static str,
+        /// The malformed part
+        value: String,
+    },
+    /// Range whose start is above its end
+    #[error("empty range '{value}' in '{input}': start is greater than end")]
+    EmptyRange {
+        /// The whole pattern
+        input: String,
+        /// The range part
+        value: String,
+    },
+}
@@ -0,0 +1,119 @@
+//! Timestamped attach and detach records for machine-readable event streams
+
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::UsbDevice;
+use crate::live::TreeChange;
+use crate::path::DevicePath;
+
+/// Whether a device was attached or detached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// The device was plugged in
+    Attach,
+    /// The device was unplugged
+    Detach,
+}
+
+/// One attach or detach, as written to a JSON Lines stream
+///
+/// # Examples
+///
+/// ```
+/// use std::time::SystemTime;
+/// use usbinfo::{EventRecord, TreeChange, UsbDevice};
+///
+/// let device = UsbDevice { bus: 1, port_path: vec![2], ..Default::default() };
+/// let change = TreeChange::Added { path: device.path(), device };
+///
+/// for record in EventRecord::from_change(&change, SystemTime::now()) {
+///     println!("{}", serde_json::to_string(&record).unwrap());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventRecord {
+    /// When the event was seen, written as RFC 3339 in UTC
+    #[serde(serialize_with = "timestamp::serialize")]
+    pub timestamp: SystemTime,
+    /// Attach or detach
+    #[serde(rename = "event")]
+    pub kind: EventKind,
+    /// Path of the device
+    pub path: DevicePath,
+    /// The device record; for detaches, as it was last seen
+    pub device: UsbDevice,
+}
+
+impl EventRecord {
+    /// Describe a tree change as attach and detach records
+    ///
+    /// A `Changed` path becomes a detach of the old device followed by an
+    /// attach of the new one.
+    pub fn from_change(change: &TreeChange, timestamp: SystemTime) -> Vec<EventRecord> {
+        let record = |kind, path: &DevicePath, device: &UsbDevice| EventRecord {
+            timestamp,
+            kind,
+            path: path.clone(),
+            device: device.clone(),
+        };
+        match change {
+            TreeChange::Added { path, device } => vec![record(EventKind::Attach, path, device)],
+            TreeChange::Removed { path, device } => vec![record(EventKind::Detach, path, device)],
+            TreeChange::Changed { path, old, new } => vec![
+                record(EventKind::Detach, path, old),
+                record(EventKind::Attach, path, new),
+            ],
+        }
+    }
+}
+
+/// Serde helpers for times written as RFC 3339 in UTC, with milliseconds
+pub(crate) mod timestamp {
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::test_support::device;
+
+    #[test]
+    fn test_json_record() {
+        let old = device("1:2.3")
+            .with_address(4)
+            .with_id(0x0403, 0x6001)
+            .with_speed(nusb::Speed::Full)
+            .build();
+        let new = UsbDevice { address: 5, ..old.clone() };
+        let change = TreeChange::Changed { path: old.path(), old, new };
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+
+        let records = EventRecord::from_change(&change, time);
+        let kinds: Vec<EventKind> = records.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, [EventKind::Detach, EventKind::Attach]);
+
+        let json = serde_json::to_value(&records[1]).unwrap();
+        assert_eq!(json["timestamp"], "2023-11-14T22:13:20.250Z");
+        assert_eq!(json["event"], "attach");
+        assert_eq!(json["path"], "1:2.3");
+        assert_eq!(json["device"]["address"], 5);
+        assert_eq!(json["device"]["vid"], 0x0403);
+        assert_eq!(json["device"]["speed"], "full");
+    }
+}
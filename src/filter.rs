@@ -0,0 +1,688 @@
+//! Filter expression language for selecting devices
+//!
+//! ```text
+//! expr       := or
+//! or         := and ("or" and)*
+//! and        := not ("and" not)*
+//! not        := "not" not | primary
+//! primary    := "(" expr ")" | "under" PATH | FIELD OP VALUE
+//! OP         := "=" | "!=" | "<" | "<=" | ">" | ">=" | "~" | "!~"
+//! ```
+//!
+//! Numeric fields are `vid`, `pid` (hexadecimal), `class`, `subclass`,
+//! `protocol`, `bus`, `address` and `depth`, plus the versions `bcdusb` and
+//! `bcddevice` (alias `firmware`) written as `1.20`. `class` matches if the
+//! device class or any interface class does, so `class=hid` finds keyboards
+//! that declare class 0 and HID interfaces; `class!=hid` needs all of them
+//! to differ. Text fields are `serial`, `manufacturer`, `product` and
+//! `name`; they support `=`, `!=` and the regular expression operators `~`
+//! and `!~`. `speed` compares by speed
+//! (`speed>=high`), `path` compares for equality or matches a
+//! [`DevicePathPattern`](crate::DevicePathPattern) with `~`, and `vidpid` matches a
+//! [`VidPidPattern`](crate::VidPidPattern) such as `0403:*` with `=` or `!=`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use nusb::Speed;
+use regex::Regex;
+
+use crate::device::{parse_bcd, parse_class, parse_speed, UsbDevice};
+use crate::error::{FilterError, FilterErrorKind};
+use crate::path::{DevicePath, DevicePathPattern};
+use crate::vidpid::VidPidPattern;
+
+/// Comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+impl Op {
+    fn is_ordering(self) -> bool {
+        matches!(self, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+
+    fn is_regex(self) -> bool {
+        matches!(self, Op::Match | Op::NotMatch)
+    }
+
+    fn compare<T: PartialOrd>(self, lhs: &T, rhs: &T) -> bool {
+        match self {
+            Op::Eq => lhs == rhs,
+            Op::Ne => lhs != rhs,
+            Op::Lt => lhs < rhs,
+            Op::Le => lhs <= rhs,
+            Op::Gt => lhs > rhs,
+            Op::Ge => lhs >= rhs,
+            Op::Match | Op::NotMatch => false,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Match => "~",
+            Op::NotMatch => "!~",
+        }
+    }
+}
+
+/// Numeric device fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumField {
+    Vid,
+    Pid,
+    Class,
+    Subclass,
+    Protocol,
+    Bus,
+    Address,
+    Depth,
+    BcdUsb,
+    BcdDevice,
+}
+
+impl NumField {
+    fn get(self, device: &UsbDevice, path: &DevicePath) -> u32 {
+        match self {
+            NumField::Vid => device.vid.into(),
+            NumField::Pid => device.pid.into(),
+            NumField::Class => device.class.into(),
+            NumField::Subclass => device.subclass.into(),
+            NumField::Protocol => device.protocol.into(),
+            NumField::Bus => path.bus().into(),
+            NumField::Address => device.address.into(),
+            NumField::Depth => path.depth() as u32,
+            NumField::BcdUsb => device.bcd_usb.into(),
+            NumField::BcdDevice => device.bcd_device.into(),
+        }
+    }
+
+    fn parse_value(self, s: &str) -> Option<u32> {
+        match self {
+            NumField::Vid | NumField::Pid => {
+                u16::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16)
+                    .ok()
+                    .map(u32::from)
+            }
+            NumField::Class => parse_class(s).map(u32::from),
+            NumField::BcdUsb | NumField::BcdDevice => parse_bcd(s).map(u32::from),
+            _ => match s.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16).ok().map(u32::from),
+                None => s.parse::<u8>().ok().map(u32::from),
+            },
+        }
+    }
+}
+
+/// Text device fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextField {
+    Serial,
+    Manufacturer,
+    Product,
+    Name,
+}
+
+impl TextField {
+    fn get(self, device: &UsbDevice) -> Option<&str> {
+        match self {
+            TextField::Serial => device.serial.as_deref(),
+            TextField::Manufacturer => device.manufacturer.as_deref(),
+            TextField::Product => device.product.as_deref(),
+            TextField::Name => Some(device.name.as_str()),
+        }
+    }
+}
+
+/// Right-hand side of a text comparison
+#[derive(Debug, Clone)]
+enum TextValue {
+    Exact(String),
+    Pattern(Regex),
+}
+
+/// Parsed filter expression
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Num(NumField, Op, u32),
+    Text(TextField, Op, TextValue),
+    Speed(Op, Speed),
+    Path(Op, DevicePath),
+    PathPattern(Op, DevicePathPattern),
+    VidPid(Op, VidPidPattern),
+    Under(DevicePath),
+}
+
+impl Expr {
+    fn eval(&self, device: &UsbDevice, path: &DevicePath) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(device, path) && b.eval(device, path),
+            Expr::Or(a, b) => a.eval(device, path) || b.eval(device, path),
+            Expr::Not(e) => !e.eval(device, path),
+            Expr::Num(NumField::Class, op, value) => {
+                let mut classes = device_classes(device).map(u32::from);
+                if *op == Op::Ne {
+                    classes.all(|class| class != *value)
+                } else {
+                    classes.any(|class| op.compare(&class, value))
+                }
+            }
+            Expr::Num(field, op, value) => op.compare(&field.get(device, path), value),
+            Expr::Text(field, op, value) => {
+                let matched = match (field.get(device), value) {
+                    (None, _) => false,
+                    (Some(text), TextValue::Exact(s)) => text == s,
+                    (Some(text), TextValue::Pattern(re)) => re.is_match(text),
+                };
+                matched == matches!(op, Op::Eq | Op::Match)
+            }
+            Expr::Speed(op, value) => device.speed.is_some_and(|s| op.compare(&s, value)),
+            Expr::Path(op, value) => (path == value) == (*op == Op::Eq),
+            Expr::PathPattern(op, pattern) => pattern.matches(path) == (*op == Op::Match),
+            Expr::VidPid(op, pattern) => pattern.matches(device) == (*op == Op::Eq),
+            Expr::Under(ancestor) => path.is_descendant_of(ancestor),
+        }
+    }
+}
+
+/// The device class, unless it is 0 and left to the interfaces, then the
+/// interface classes
+fn device_classes(device: &UsbDevice) -> impl Iterator<Item = u8> + '_ {
+    let own = (device.class != 0 || device.interface_classes.is_empty()).then_some(device.class);
+    own.into_iter().chain(device.interface_classes.iter().copied())
+}
+
+/// Lexical token
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(Op),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "{}", w),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Op(op) => write!(f, "{}", op.as_str()),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+/// Split the input into tokens paired with their byte offsets
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push((pos, Token::LParen));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((pos, Token::RParen));
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        // Only quotes and backslashes are escapes; keep regex escapes intact
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\'))) => text.push(escaped),
+                            Some((_, other)) => {
+                                text.push('\\');
+                                text.push(other);
+                            }
+                            None => return Err(FilterError::new(FilterErrorKind::UnterminatedString, pos)),
+                        },
+                        Some((_, ch)) => text.push(ch),
+                        None => return Err(FilterError::new(FilterErrorKind::UnterminatedString, pos)),
+                    }
+                }
+                tokens.push((pos, Token::Str(text)));
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                chars.next();
+                let next_eq = chars.next_if(|&(_, n)| n == '=').is_some();
+                let op = match (c, next_eq) {
+                    ('=', false) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    ('~', false) => Op::Match,
+                    ('!', false) if chars.next_if(|&(_, n)| n == '~').is_some() => Op::NotMatch,
+                    _ => return Err(FilterError::new(FilterErrorKind::UnexpectedChar(c), pos)),
+                };
+                tokens.push((pos, Token::Op(op)));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some((_, ch)) =
+                    chars.next_if(|&(_, ch)| !ch.is_whitespace() && !"()\"=!<>~".contains(ch))
+                {
+                    word.push(ch);
+                }
+                tokens.push((pos, Token::Word(word)));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the token stream
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    /// Byte offset of the current token, or the end of input
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, kind: FilterErrorKind) -> FilterError {
+        FilterError::new(kind, self.offset())
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_keyword("or") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let mut lhs = self.parse_not()?;
+        while self.eat_keyword("and") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, FilterError> {
+        if self.eat_keyword("not") {
+            Ok(Expr::Not(Box::new(self.parse_not()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, FilterError> {
+        let start = self.offset();
+        match self.next() {
+            Some((_, Token::LParen)) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some((_, Token::RParen)) => Ok(expr),
+                    _ => Err(FilterError::new(FilterErrorKind::UnclosedParen, start)),
+                }
+            }
+            Some((_, Token::Word(w))) if w.eq_ignore_ascii_case("under") => {
+                let (pos, value) = self.parse_value()?;
+                let path = value.parse().map_err(|_| {
+                    FilterError::new(
+                        FilterErrorKind::InvalidValue {
+                            field: "under".to_string(),
+                            value: value.clone(),
+                        },
+                        pos,
+                    )
+                })?;
+                Ok(Expr::Under(path))
+            }
+            Some((_, Token::Word(field))) => self.parse_comparison(field, start),
+            Some((pos, token)) => Err(FilterError::new(
+                FilterErrorKind::UnexpectedToken(token.to_string()),
+                pos,
+            )),
+            None => Err(self.error(FilterErrorKind::ExpectedExpression)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<(usize, String), FilterError> {
+        match self.next() {
+            Some((pos, Token::Word(v))) | Some((pos, Token::Str(v))) => Ok((pos, v)),
+            Some((pos, token)) => Err(FilterError::new(
+                FilterErrorKind::UnexpectedToken(token.to_string()),
+                pos,
+            )),
+            None => Err(FilterError::new(FilterErrorKind::ExpectedValue, self.end)),
+        }
+    }
+
+    fn parse_comparison(&mut self, field: String, start: usize) -> Result<Expr, FilterError> {
+        let op_pos = self.offset();
+        let op = match self.next() {
+            Some((_, Token::Op(op))) => op,
+            _ => return Err(FilterError::new(FilterErrorKind::ExpectedOperator, op_pos)),
+        };
+        let (value_pos, value) = self.parse_value()?;
+
+        let invalid_op = || {
+            FilterError::new(
+                FilterErrorKind::InvalidOperator {
+                    field: field.clone(),
+                    op: op.as_str().to_string(),
+                },
+                op_pos,
+            )
+        };
+        let invalid_value = || {
+            FilterError::new(
+                FilterErrorKind::InvalidValue {
+                    field: field.clone(),
+                    value: value.clone(),
+                },
+                value_pos,
+            )
+        };
+
+        let num_field = match field.to_ascii_lowercase().as_str() {
+            "vid" => Some(NumField::Vid),
+            "pid" => Some(NumField::Pid),
+            "class" => Some(NumField::Class),
+            "subclass" => Some(NumField::Subclass),
+            "protocol" => Some(NumField::Protocol),
+            "bus" => Some(NumField::Bus),
+            "address" => Some(NumField::Address),
+            "depth" => Some(NumField::Depth),
+            "bcdusb" => Some(NumField::BcdUsb),
+            "bcddevice" | "firmware" => Some(NumField::BcdDevice),
+            _ => None,
+        };
+        if let Some(num_field) = num_field {
+            if op.is_regex() {
+                return Err(invalid_op());
+            }
+            let parsed = num_field.parse_value(&value).ok_or_else(invalid_value)?;
+            return Ok(Expr::Num(num_field, op, parsed));
+        }
+
+        let text_field = match field.to_ascii_lowercase().as_str() {
+            "serial" => Some(TextField::Serial),
+            "manufacturer" => Some(TextField::Manufacturer),
+            "product" => Some(TextField::Product),
+            "name" => Some(TextField::Name),
+            _ => None,
+        };
+        if let Some(text_field) = text_field {
+            let text_value = match op {
+                Op::Eq | Op::Ne => TextValue::Exact(value),
+                Op::Match | Op::NotMatch => TextValue::Pattern(Regex::new(&value).map_err(|e| {
+                    FilterError::new(FilterErrorKind::InvalidRegex(e.to_string()), value_pos)
+                })?),
+                _ => return Err(invalid_op()),
+            };
+            return Ok(Expr::Text(text_field, op, text_value));
+        }
+
+        match field.to_ascii_lowercase().as_str() {
+            "speed" => {
+                if op.is_regex() {
+                    return Err(invalid_op());
+                }
+                let speed = parse_speed(&value).ok_or_else(invalid_value)?;
+                Ok(Expr::Speed(op, speed))
+            }
+            "path" if op.is_regex() => {
+                let pattern = value.parse().map_err(|_| invalid_value())?;
+                Ok(Expr::PathPattern(op, pattern))
+            }
+            "path" => {
+                if op.is_ordering() {
+                    return Err(invalid_op());
+                }
+                let path = value.parse().map_err(|_| invalid_value())?;
+                Ok(Expr::Path(op, path))
+            }
+            "vidpid" => {
+                if op.is_regex() || op.is_ordering() {
+                    return Err(invalid_op());
+                }
+                let pattern: VidPidPattern = value.parse().map_err(|_| invalid_value())?;
+                if pattern.negated {
+                    return Err(invalid_value());
+                }
+                Ok(Expr::VidPid(op, pattern))
+            }
+            _ => Err(FilterError::new(FilterErrorKind::UnknownField(field), start)),
+        }
+    }
+}
+
+/// A compiled device filter expression
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::Filter;
+///
+/// let filter: Filter = r#"vid=0x0483 and (class=hid or serial~"^ABC") and speed>=high and under 1:2"#
+///     .parse()
+///     .unwrap();
+/// println!("{}", filter);
+///
+/// let err = "vid=0x0483 and".parse::<Filter>().unwrap_err();
+/// assert_eq!(err.position, 14);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    /// Check whether a device matches, using the device's own path
+    pub fn matches(&self, device: &UsbDevice) -> bool {
+        self.matches_at(device, &device.path())
+    }
+
+    /// Check whether a device located at `path` matches
+    pub fn matches_at(&self, device: &UsbDevice, path: &DevicePath) -> bool {
+        self.expr.eval(device, path)
+    }
+
+    /// The expression text this filter was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let mut parser = Parser {
+                tokens: tokenize(s)?,
+                pos: 0,
+                end: s.len(),
+            };
+            let expr = parser.parse_or()?;
+            if let Some(token) = parser.peek() {
+                return Err(parser.error(FilterErrorKind::UnexpectedToken(token.to_string())));
+            }
+            Ok(expr)
+        };
+        let expr = parse().map_err(|e| e.in_source(s))?;
+        Ok(Filter {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::device;
+
+    fn eval(expr: &str) -> bool {
+        let device = device("1:2.4")
+            .with_id(0x0483, 0x5740)
+            .with_address(9)
+            .with_name("Virtual COM Port")
+            .with_manufacturer("STMicroelectronics")
+            .with_product("Virtual COM Port")
+            .with_serial("ABC123")
+            .with_class(0x03)
+            .with_speed(Speed::Full)
+            .with_bcd(0x0200, 0x0119)
+            .build();
+        expr.parse::<Filter>().unwrap().matches(&device)
+    }
+
+    #[test]
+    fn test_numeric_fields() {
+        assert!(eval("vid=0x0483"));
+        assert!(eval("vid=483 and pid=5740"));
+        assert!(eval("class=hid"));
+        assert!(eval("depth>=2 and bus=1"));
+        assert!(!eval("pid!=5740"));
+        assert!(eval("bcdDevice<1.20"));
+        assert!(eval("firmware=1.19 and bcdusb>=2.0"));
+        assert!(!eval("bcddevice<1.1"));
+        assert!("firmware<1.x".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn test_text_fields() {
+        assert!(eval(r#"serial~"^ABC""#));
+        assert!(eval(r#"manufacturer="STMicroelectronics""#));
+        assert!(eval("serial!~^XYZ"));
+        assert!(eval(r#"serial~"^[A-Z]+\d{3}$""#));
+        assert!(!eval(r#"product="Other""#));
+    }
+
+    #[test]
+    fn test_speed_and_path() {
+        assert!(eval("speed>=full"));
+        assert!(!eval("speed>=high"));
+        assert!(eval("under 1:2"));
+        assert!(!eval("under 1:2.4"));
+        assert!(eval("path=1:2.4"));
+        assert!(eval("path~*:2.{3,4}"));
+        assert!(eval("path!~1:**.5"));
+        assert!(eval("vidpid=0483:5700-57ff"));
+        assert!(eval("vidpid!=0403:*"));
+    }
+
+    #[test]
+    fn test_interface_classes() {
+        let serial = device("1:3").with_interface_classes(&[0x02, 0x0a]).build();
+        let matches = |expr: &str| expr.parse::<Filter>().unwrap().matches(&serial);
+        assert!(matches("class=2"));
+        assert!(matches("class=10 and class!=hid"));
+        assert!(!matches("class=0"));
+        assert!(!matches("class!=2"));
+
+        let composite = device("1:4").with_class(0xef).with_interface_classes(&[0x02, 0x03]).build();
+        assert!("class=hid".parse::<Filter>().unwrap().matches(&composite));
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert!(eval("vid=0x0483 and (class=hub or serial~\"^ABC\") and speed>=full"));
+        assert!(eval("vid=ffff and pid=ffff or class=hid"));
+        assert!(!eval("not class=hid"));
+        assert!(eval("not not class=hid"));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let err = "vid=0x0483 and".parse::<Filter>().unwrap_err();
+        assert_eq!(err.kind, FilterErrorKind::ExpectedExpression);
+        assert_eq!(err.position, 14);
+
+        let err = "colour=red".parse::<Filter>().unwrap_err();
+        assert_eq!(err.kind, FilterErrorKind::UnknownField("colour".to_string()));
+        assert_eq!(err.position, 0);
+
+        let err = "vid=1 and serial<5".parse::<Filter>().unwrap_err();
+        assert!(matches!(err.kind, FilterErrorKind::InvalidOperator { .. }));
+        assert_eq!(err.position, 16);
+
+        let err = "(vid=1".parse::<Filter>().unwrap_err();
+        assert_eq!(err.kind, FilterErrorKind::UnclosedParen);
+        assert_eq!(err.position, 0);
+
+        let err = "speed=warp".parse::<Filter>().unwrap_err();
+        assert!(matches!(err.kind, FilterErrorKind::InvalidValue { .. }));
+        assert_eq!(err.position, 6);
+
+        let err = r#"serial="abc"#.parse::<Filter>().unwrap_err();
+        assert_eq!(err.kind, FilterErrorKind::UnterminatedString);
+        assert_eq!(err.position, 7);
+    }
+
+    #[test]
+    fn test_error_column_counts_characters() {
+        let err = r#"product="Überhub" and spede=high"#.parse::<Filter>().unwrap_err();
+        assert_eq!(err.kind, FilterErrorKind::UnknownField("spede".to_string()));
+        assert_eq!(err.position, 23);
+        assert_eq!(err.column, 23);
+        assert!(err.to_string().ends_with("at column 23"));
+        let caret = err.annotate(r#"product="Überhub" and spede=high"#);
+        assert_eq!(caret.lines().nth(1).unwrap().find('^'), Some(22));
+    }
+}
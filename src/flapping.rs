@@ -0,0 +1,463 @@
+//! Reconnect counting and flapping detection per port and per device
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use colored::Colorize;
+use serde::{Serialize, Serializer};
+
+use crate::device::UsbDevice;
+use crate::events::{timestamp, EventKind, EventRecord};
+use crate::path::DevicePath;
+use crate::tree::UsbTree;
+
+/// When a port or device counts as flapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FlapThreshold {
+    /// Number of disconnects that make a port or device flap
+    pub disconnects: usize,
+    /// Time window the disconnects must fall within
+    #[serde(rename = "window_ms", serialize_with = "millis")]
+    pub window: Duration,
+}
+
+impl Default for FlapThreshold {
+    fn default() -> Self {
+        Self {
+            disconnects: 3,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// What makes a device the same device across reconnects
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct DeviceIdentity {
+    /// Vendor ID
+    pub vid: u16,
+    /// Product ID
+    pub pid: u16,
+    /// Serial number
+    pub serial: String,
+}
+
+impl DeviceIdentity {
+    /// Get the identity of a device
+    ///
+    /// Devices without a serial number have none: identical hubs or
+    /// adapters could not be told apart, so they are only tracked by port.
+    pub fn of(device: &UsbDevice) -> Option<Self> {
+        Some(Self {
+            vid: device.vid,
+            pid: device.pid,
+            serial: device.serial.clone()?,
+        })
+    }
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x} {}", self.vid, self.pid, self.serial)
+    }
+}
+
+/// Something that was flagged as flapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlapTarget {
+    /// A port, whatever is plugged into it
+    Port(DevicePath),
+    /// A device with a serial number, wherever it is plugged in
+    Device(DeviceIdentity),
+}
+
+impl fmt::Display for FlapTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlapTarget::Port(path) => write!(f, "port {}", path),
+            FlapTarget::Device(identity) => write!(f, "device {}", identity),
+        }
+    }
+}
+
+/// Connects, disconnects and connection durations of a port or device
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConnectionStats {
+    /// Times it was connected while watching
+    pub connects: usize,
+    /// Times it was disconnected while watching
+    pub disconnects: usize,
+    /// Shortest time it stayed connected
+    #[serde(rename = "shortest_connection_ms", serialize_with = "optional_millis")]
+    pub shortest_connection: Option<Duration>,
+    /// Longest time it stayed disconnected before coming back
+    #[serde(rename = "longest_outage_ms", serialize_with = "optional_millis")]
+    pub longest_outage: Option<Duration>,
+    /// When it was last connected or disconnected
+    #[serde(serialize_with = "optional_timestamp")]
+    pub last_change: Option<SystemTime>,
+    /// Whether it has crossed the flapping threshold
+    pub flapping: bool,
+    #[serde(skip)]
+    connected_since: Option<SystemTime>,
+    #[serde(skip)]
+    disconnected_since: Option<SystemTime>,
+    #[serde(skip)]
+    recent_disconnects: VecDeque<SystemTime>,
+}
+
+impl ConnectionStats {
+    fn connect(&mut self, time: SystemTime) {
+        self.connects += 1;
+        if let Some(since) = self.disconnected_since.take() {
+            let outage = time.duration_since(since).unwrap_or_default();
+            self.longest_outage = self.longest_outage.max(Some(outage));
+        }
+        self.connected_since = Some(time);
+        self.last_change = Some(time);
+    }
+
+    /// Count a disconnect, returning whether it made this start flapping
+    fn disconnect(&mut self, time: SystemTime, threshold: &FlapThreshold) -> bool {
+        self.disconnects += 1;
+        if let Some(since) = self.connected_since.take() {
+            let connection = time.duration_since(since).unwrap_or_default();
+            self.shortest_connection = Some(self.shortest_connection.map_or(connection, |d| d.min(connection)));
+        }
+        self.disconnected_since = Some(time);
+        self.last_change = Some(time);
+
+        self.recent_disconnects.push_back(time);
+        while let Some(&oldest) = self.recent_disconnects.front()
+            && time.duration_since(oldest).unwrap_or_default() > threshold.window
+        {
+            self.recent_disconnects.pop_front();
+        }
+        let started = !self.flapping && self.recent_disconnects.len() >= threshold.disconnects;
+        self.flapping |= started;
+        started
+    }
+}
+
+/// Tracks reconnects per port and per device and flags the ones that flap
+///
+/// Only devices with a serial number are tracked per device; see
+/// [`DeviceIdentity::of`].
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use usbinfo::{EventKind, EventRecord, FlapThreshold, FlapTracker, UsbDevice};
+///
+/// let threshold = FlapThreshold { disconnects: 2, window: Duration::from_secs(10) };
+/// let mut tracker = FlapTracker::new(threshold);
+///
+/// let serial = Some("A1B2C3".to_string());
+/// let device = UsbDevice { bus: 1, port_path: vec![2], serial, ..Default::default() };
+/// let start = SystemTime::now();
+/// let mut flagged = Vec::new();
+/// for (i, kind) in [EventKind::Detach, EventKind::Attach, EventKind::Detach].into_iter().enumerate() {
+///     let timestamp = start + Duration::from_secs(i as u64);
+///     let record = EventRecord { timestamp, kind, path: device.path(), device: device.clone() };
+///     flagged.extend(tracker.record(&record));
+/// }
+/// assert_eq!(flagged.len(), 2); // the port and the device
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FlapTracker {
+    threshold: FlapThreshold,
+    ports: BTreeMap<DevicePath, ConnectionStats>,
+    devices: BTreeMap<DeviceIdentity, ConnectionStats>,
+}
+
+impl FlapTracker {
+    /// Create a tracker flagging ports and devices above `threshold`
+    ///
+    /// # Panics
+    ///
+    /// If `threshold.disconnects` is zero.
+    pub fn new(threshold: FlapThreshold) -> Self {
+        assert!(threshold.disconnects > 0, "flapping threshold must be at least one disconnect");
+        Self {
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    /// Note the devices connected when watching starts
+    ///
+    /// They are not counted as connects, but their first disconnect then
+    /// has a connection duration.
+    pub fn start(&mut self, tree: &UsbTree<UsbDevice>, time: SystemTime) {
+        for (path, device) in tree.all_devices() {
+            self.ports.entry(path.clone()).or_default().connected_since = Some(time);
+            if let Some(identity) = DeviceIdentity::of(device) {
+                self.devices.entry(identity).or_default().connected_since = Some(time);
+            }
+        }
+    }
+
+    /// Count an attach or detach, returning what started flapping
+    pub fn record(&mut self, record: &EventRecord) -> Vec<FlapTarget> {
+        let port = self.ports.entry(record.path.clone()).or_default();
+        let identity = DeviceIdentity::of(&record.device);
+        let mut device = identity
+            .clone()
+            .map(|identity| self.devices.entry(identity).or_default());
+
+        let mut flagged = Vec::new();
+        match record.kind {
+            EventKind::Attach => {
+                port.connect(record.timestamp);
+                if let Some(ref mut device) = device {
+                    device.connect(record.timestamp);
+                }
+            }
+            EventKind::Detach => {
+                if port.disconnect(record.timestamp, &self.threshold) {
+                    flagged.push(FlapTarget::Port(record.path.clone()));
+                }
+                if let (Some(device), Some(identity)) = (device, identity)
+                    && device.disconnect(record.timestamp, &self.threshold)
+                {
+                    flagged.push(FlapTarget::Device(identity));
+                }
+            }
+        }
+        flagged
+    }
+
+    /// Statistics per port, in path order
+    pub fn ports(&self) -> impl Iterator<Item = (&DevicePath, &ConnectionStats)> {
+        self.ports.iter()
+    }
+
+    /// Statistics per device with a serial number
+    pub fn devices(&self) -> impl Iterator<Item = (&DeviceIdentity, &ConnectionStats)> {
+        self.devices.iter()
+    }
+
+    /// Summarize the ports and devices that connected or disconnected
+    ///
+    /// Flapping ones come first, then the most disconnected.
+    pub fn report(&self) -> FlapReport {
+        fn active<'a, K: Clone + 'a>(
+            stats: impl Iterator<Item = (&'a K, &'a ConnectionStats)>,
+        ) -> Vec<(K, ConnectionStats)> {
+            let mut active: Vec<(K, ConnectionStats)> = stats
+                .filter(|(_, s)| s.connects + s.disconnects > 0)
+                .map(|(key, s)| (key.clone(), s.clone()))
+                .collect();
+            active.sort_by_key(|(_, s)| (!s.flapping, Reverse(s.disconnects)));
+            active
+        }
+        FlapReport {
+            generated: SystemTime::now(),
+            threshold: self.threshold,
+            ports: active(self.ports())
+                .into_iter()
+                .map(|(path, stats)| PortReport { path, stats })
+                .collect(),
+            devices: active(self.devices())
+                .into_iter()
+                .map(|(identity, stats)| DeviceReport { identity, stats })
+                .collect(),
+        }
+    }
+}
+
+/// Reconnect statistics of one port
+#[derive(Debug, Clone, Serialize)]
+pub struct PortReport {
+    /// Path of the port
+    pub path: DevicePath,
+    /// Its statistics
+    #[serde(flatten)]
+    pub stats: ConnectionStats,
+}
+
+/// Reconnect statistics of one device
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceReport {
+    /// Which device
+    #[serde(flatten)]
+    pub identity: DeviceIdentity,
+    /// Its statistics
+    #[serde(flatten)]
+    pub stats: ConnectionStats,
+}
+
+/// Reconnect summary of a [`FlapTracker`]
+///
+/// Serializes to JSON for other tools, and displays as one table for
+/// ports and one for devices, with flapping rows colored.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlapReport {
+    /// When the report was made
+    #[serde(with = "timestamp")]
+    pub generated: SystemTime,
+    /// The threshold used for flagging
+    pub threshold: FlapThreshold,
+    /// Ports that connected or disconnected
+    pub ports: Vec<PortReport>,
+    /// Devices with a serial number that connected or disconnected
+    pub devices: Vec<DeviceReport>,
+}
+
+impl FlapReport {
+    /// Check whether anything flapped
+    pub fn any_flapping(&self) -> bool {
+        self.ports.iter().any(|p| p.stats.flapping) || self.devices.iter().any(|d| d.stats.flapping)
+    }
+}
+
+impl fmt::Display for FlapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ports.is_empty() {
+            return writeln!(f, "No reconnects");
+        }
+        let ports = self.ports.iter().map(|p| (p.path.to_string(), &p.stats));
+        write_table(f, "PORT", ports)?;
+        // Devices without a serial number are only counted by port
+        if self.devices.is_empty() {
+            return Ok(());
+        }
+        writeln!(f)?;
+        let devices = self.devices.iter().map(|d| (d.identity.to_string(), &d.stats));
+        write_table(f, "DEVICE", devices)
+    }
+}
+
+fn write_table<'a>(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    rows: impl Iterator<Item = (String, &'a ConnectionStats)> + Clone,
+) -> fmt::Result {
+    let width = rows.clone().map(|(key, _)| key.len()).chain([title.len()]).max().unwrap_or(0);
+    let duration = |d: Option<Duration>| {
+        d.map_or_else(
+            || "-".to_string(),
+            |d| humantime::format_duration(Duration::from_millis(d.as_millis() as u64)).to_string(),
+        )
+    };
+    writeln!(
+        f,
+        "{:<width$}  {:>8}  {:>11}  {:>16}  {:>13}",
+        title, "CONNECTS", "DISCONNECTS", "SHORTEST CONNECT", "LONGEST OUTAGE"
+    )?;
+    for (key, stats) in rows {
+        let row = format!(
+            "{:<width$}  {:>8}  {:>11}  {:>16}  {:>13}",
+            key,
+            stats.connects,
+            stats.disconnects,
+            duration(stats.shortest_connection),
+            duration(stats.longest_outage),
+        );
+        if stats.flapping {
+            writeln!(f, "{}  {}", row.red(), "FLAPPING".red().bold())?;
+        } else {
+            writeln!(f, "{}", row)?;
+        }
+    }
+    Ok(())
+}
+
+fn millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+fn optional_millis<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.map(|d| d.as_millis()).serialize(serializer)
+}
+
+fn optional_timestamp<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => timestamp::serialize(time, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::device;
+
+    fn record(kind: EventKind, path: &str, serial: &str, ms: u64) -> EventRecord {
+        let mut device = device(path).with_id(0x0403, 0x6001);
+        if !serial.is_empty() {
+            device = device.with_serial(serial);
+        }
+        let device = device.build();
+        EventRecord {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(ms),
+            kind,
+            path: device.path(),
+            device,
+        }
+    }
+
+    #[test]
+    fn test_flapping_port_and_device() {
+        let threshold = FlapThreshold {
+            disconnects: 2,
+            window: Duration::from_secs(1),
+        };
+        let mut tracker = FlapTracker::new(threshold);
+        let events = [
+            // A moves between ports, slowly
+            record(EventKind::Attach, "1:1", "A", 0),
+            record(EventKind::Detach, "1:1", "A", 5_000),
+            record(EventKind::Attach, "1:2", "A", 5_500),
+            record(EventKind::Detach, "1:2", "A", 9_000),
+            // B and C on a bad port 3
+            record(EventKind::Attach, "1:3", "B", 10_000),
+            record(EventKind::Detach, "1:3", "B", 10_100),
+            record(EventKind::Attach, "1:3", "C", 10_400),
+            record(EventKind::Detach, "1:3", "C", 10_500),
+            // Two serial-less adapters are not mistaken for one flapping device
+            record(EventKind::Detach, "1:4", "", 11_000),
+            record(EventKind::Detach, "1:5", "", 11_000),
+        ];
+        let flagged: Vec<String> = events
+            .iter()
+            .flat_map(|e| tracker.record(e))
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(flagged, ["port 1:3"]);
+
+        let report = tracker.report();
+        assert!(report.any_flapping());
+        let port = &report.ports[0];
+        assert_eq!((port.path.to_string(), port.stats.disconnects), ("1:3".to_string(), 2));
+        assert_eq!(port.stats.shortest_connection, Some(Duration::from_millis(100)));
+        assert_eq!(port.stats.longest_outage, Some(Duration::from_millis(300)));
+
+        assert_eq!(report.devices.len(), 3);
+        let a = report.devices.iter().find(|d| d.identity.serial == "A").unwrap();
+        assert_eq!((a.stats.connects, a.stats.disconnects, a.stats.flapping), (2, 2, false));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["threshold"]["window_ms"], 1000);
+        assert_eq!(json["ports"][0]["path"], "1:3");
+        assert_eq!(json["ports"][0]["flapping"], true);
+        assert_eq!(json["devices"][0]["serial"], "A");
+        assert_eq!(json["devices"][0]["shortest_connection_ms"], 3500);
+    }
+
+    #[test]
+    fn test_report_without_devices() {
+        let mut tracker = FlapTracker::new(FlapThreshold {
+            disconnects: 2,
+            window: Duration::from_secs(1),
+        });
+        assert_eq!(tracker.report().to_string(), "No reconnects\n");
+
+        tracker.record(&record(EventKind::Detach, "1:4", "", 0));
+        let text = tracker.report().to_string();
+        assert!(text.starts_with("PORT"));
+        assert!(!text.contains("DEVICE"));
+    }
+}
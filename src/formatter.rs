@@ -1,37 +1,50 @@
 //! Formatting and display for USB device trees
 
+use std::collections::HashSet;
 use std::fmt;
-use colored::{ColoredString, Colorize};
+use std::str::FromStr;
+use colored::Colorize;
+use serde::Deserialize;
 
-use crate::device::UsbDevice;
-use crate::tree::{PortTree, UsbTree};
+use crate::device::{class_name, speed_name, UsbDevice};
+use crate::error::FormatError;
+use crate::labels::Labels;
+use crate::path::DevicePath;
+use crate::ports::PortMap;
+use crate::theme::{ColorMode, Theme};
+use crate::tree::UsbTree;
+use crate::visit::{VisitNode, Visitor};
 
 /// Configuration for tree output formatting
 #[derive(Debug, Clone)]
 pub struct TreeStyle {
-    /// Whether to use colored output
-    pub colored: bool,
-    /// Whether to show the header
+    /// When to use colored output
+    pub color: ColorMode,
+    /// Whether to show the header: the bus lines of a tree, the column
+    /// names of a list
     pub show_header: bool,
     /// Indent string for each level
     pub indent: String,
     /// Connector for non-last items
-    pub branch: &'static str,
+    pub branch: String,
     /// Connector for last items
-    pub corner: &'static str,
+    pub corner: String,
     /// Vertical line for continuing branches
-    pub vertical: &'static str,
+    pub vertical: String,
+    /// Coloring rules
+    pub theme: Theme,
 }
 
 impl Default for TreeStyle {
     fn default() -> Self {
         Self {
-            colored: true,
+            color: ColorMode::Auto,
             show_header: true,
             indent: "    ".to_string(),
-            branch: "├── ",
-            corner: "└── ",
-            vertical: "│   ",
+            branch: "├── ".to_string(),
+            corner: "└── ".to_string(),
+            vertical: "│   ".to_string(),
+            theme: Theme::default(),
         }
     }
 }
@@ -45,24 +58,28 @@ impl TreeStyle {
     /// Create a plain (non-colored) style
     pub fn plain() -> Self {
         Self {
-            colored: false,
+            color: ColorMode::Never,
             ..Self::default()
         }
     }
 
     /// Create an ASCII-only style (no Unicode box drawing)
     pub fn ascii() -> Self {
-        Self {
-            branch: "|-- ",
-            corner: "`-- ",
-            vertical: "|   ",
-            ..Self::default()
-        }
+        Self::default().with_connectors("|-- ", "`-- ", "|   ")
     }
 
     /// Set whether to use colors
-    pub fn with_color(mut self, colored: bool) -> Self {
-        self.colored = colored;
+    pub fn with_color(self, colored: bool) -> Self {
+        self.with_color_mode(if colored {
+            ColorMode::Always
+        } else {
+            ColorMode::Never
+        })
+    }
+
+    /// Set when to use colors
+    pub fn with_color_mode(mut self, color: ColorMode) -> Self {
+        self.color = color;
         self
     }
 
@@ -71,10 +88,83 @@ impl TreeStyle {
         self.show_header = show_header;
         self
     }
+
+    /// Set the branch, corner and vertical connector strings
+    ///
+    /// The indent is resized to match the width of the vertical connector.
+    pub fn with_connectors(
+        mut self,
+        branch: impl Into<String>,
+        corner: impl Into<String>,
+        vertical: impl Into<String>,
+    ) -> Self {
+        self.branch = branch.into();
+        self.corner = corner.into();
+        self.vertical = vertical.into();
+        self.indent = " ".repeat(self.vertical.chars().count());
+        self
+    }
+
+    /// Set the color theme
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+}
+
+/// Predicate over tree values used for highlighting and filtering
+type Predicate<'a, T> = Box<dyn Fn(&T) -> bool + 'a>;
+
+/// Predicate over devices used for filtering lists
+type DevicePredicate<'a> = Predicate<'a, UsbDevice>;
+
+/// How a value is rendered as a row in a [`TreeFormatter`]
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{TreeFormatter, TreeLabel, UsbTree};
+///
+/// struct Asset {
+///     tag: String,
+/// }
+///
+/// impl TreeLabel for Asset {
+///     fn label(&self) -> String {
+///         format!("asset {}", self.tag)
+///     }
+/// }
+///
+/// let mut tree = UsbTree::new();
+/// tree.insert_path(&"1:2".parse().unwrap(), Asset { tag: "A-17".to_string() });
+/// let output = TreeFormatter::plain(&tree).to_string();
+/// assert_eq!(output, "Bus 001\n└── asset A-17\n\n");
+/// ```
+pub trait TreeLabel {
+    /// Text of the value's row
+    fn label(&self) -> String;
+
+    /// The USB device behind the value, used for theme colors and device labels
+    fn device(&self) -> Option<&UsbDevice> {
+        None
+    }
+}
+
+impl TreeLabel for UsbDevice {
+    fn label(&self) -> String {
+        self.to_string()
+    }
+
+    fn device(&self) -> Option<&UsbDevice> {
+        Some(self)
+    }
 }
 
 /// Formatter for rendering USB device trees
 ///
+/// Renders a `UsbTree<T>` for any `T` implementing [`TreeLabel`], or for
+/// any `T` at all with a row function via [`TreeFormatter::from_fn`].
+///
 /// # Examples
 ///
 /// ```no_run
@@ -93,127 +183,617 @@ impl TreeStyle {
 /// // ASCII style
 /// let formatter = TreeFormatter::with_style(&tree, TreeStyle::ascii());
 /// println!("{}", formatter);
+///
+/// // Highlight all FTDI devices
+/// let formatter = TreeFormatter::new(&tree).with_highlight(|dev| dev.vid == 0x0403);
+/// println!("{}", formatter);
+///
+/// // Only FTDI devices and the hubs they are plugged into
+/// let formatter = TreeFormatter::new(&tree).with_filter(|dev| dev.vid == 0x0403);
+/// println!("{}", formatter);
+///
+/// // Render a mapped tree with a row function
+/// let serials = usb_tree().unwrap().map(|dev| dev.serial);
+/// let formatter = TreeFormatter::from_fn(&serials, TreeStyle::default(), |serial| {
+///     serial.clone().unwrap_or_else(|| "-".to_string())
+/// });
+/// println!("{}", formatter);
 /// ```
-pub struct TreeFormatter<'a> {
-    tree: &'a UsbTree<UsbDevice>,
+pub struct TreeFormatter<'a, T = UsbDevice> {
+    tree: &'a UsbTree<T>,
     style: TreeStyle,
+    text: Box<dyn Fn(&T) -> String + 'a>,
+    device_of: fn(&T) -> Option<&UsbDevice>,
+    highlight: Option<Predicate<'a, T>>,
+    filter: Option<Predicate<'a, T>>,
+    labels: Option<&'a Labels>,
+    ports: Option<&'a PortMap>,
+    root: Option<DevicePath>,
 }
 
-impl<'a> TreeFormatter<'a> {
-    /// Create a new formatter with default style (colored)
-    pub fn new(tree: &'a UsbTree<UsbDevice>) -> Self {
-        Self {
-            tree,
-            style: TreeStyle::default(),
-        }
+impl<'a, T: TreeLabel> TreeFormatter<'a, T> {
+    /// Create a new formatter with default style (colored when writing to a terminal)
+    pub fn new(tree: &'a UsbTree<T>) -> Self {
+        Self::with_style(tree, TreeStyle::default())
     }
 
     /// Create a formatter with a custom style
-    pub fn with_style(tree: &'a UsbTree<UsbDevice>, style: TreeStyle) -> Self {
-        Self { tree, style }
+    pub fn with_style(tree: &'a UsbTree<T>, style: TreeStyle) -> Self {
+        let mut formatter = Self::from_fn(tree, style, T::label);
+        formatter.device_of = T::device;
+        formatter
     }
 
     /// Create a plain (non-colored) formatter
-    pub fn plain(tree: &'a UsbTree<UsbDevice>) -> Self {
+    pub fn plain(tree: &'a UsbTree<T>) -> Self {
+        Self::with_style(tree, TreeStyle::plain())
+    }
+}
+
+impl<'a, T> TreeFormatter<'a, T> {
+    /// Create a formatter that renders each row with `text`
+    ///
+    /// Rows are colored by depth, since there is no device to take
+    /// class, speed or vendor colors from.
+    pub fn from_fn(tree: &'a UsbTree<T>, style: TreeStyle, text: impl Fn(&T) -> String + 'a) -> Self {
         Self {
             tree,
-            style: TreeStyle::plain(),
+            style,
+            text: Box::new(text),
+            device_of: |_| None,
+            highlight: None,
+            filter: None,
+            labels: None,
+            ports: None,
+            root: None,
         }
     }
 
-    /// Colorize text based on depth level (if colors enabled)
-    fn colorize(&self, text: &str, depth: usize) -> String {
-        if !self.style.colored {
+    /// Highlight values matching the predicate using the theme's highlight color
+    pub fn with_highlight(mut self, predicate: impl Fn(&T) -> bool + 'a) -> Self {
+        self.highlight = Some(Box::new(predicate));
+        self
+    }
+
+    /// Only render matching values and the hub chain leading to them
+    ///
+    /// Branches without matches are pruned, matches are highlighted and the
+    /// hubs kept for context are dimmed.
+    pub fn with_filter(mut self, predicate: impl Fn(&T) -> bool + 'a) -> Self {
+        self.filter = Some(Box::new(predicate));
+        self
+    }
+
+    /// Show each device's label next to it
+    pub fn with_labels(mut self, labels: &'a Labels) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+
+    /// Show physical port names on the rows of devices plugged into them
+    pub fn with_ports(mut self, ports: &'a PortMap) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Only render the subtree rooted at the given path
+    pub fn with_root(mut self, root: DevicePath) -> Self {
+        self.root = Some(root);
+        self
+    }
+
+    /// Format a label suffix for a device row
+    fn label_suffix(&self, value: &T) -> String {
+        let Some(label) = (self.device_of)(value)
+            .and_then(|device| self.labels.and_then(|labels| labels.label_for(device)))
+        else {
+            return String::new();
+        };
+        let text = format!("[{}]", label);
+        if self.style.color.enabled() {
+            format!(" {}", text.bold())
+        } else {
+            format!(" {}", text)
+        }
+    }
+
+    /// Format a physical port name suffix for a row
+    fn port_suffix(&self, path: &DevicePath) -> String {
+        let Some(name) = self.ports.and_then(|ports| ports.name_of(path)) else {
+            return String::new();
+        };
+        let text = format!("({})", name);
+        if self.style.color.enabled() {
+            format!(" {}", text.italic())
+        } else {
+            format!(" {}", text)
+        }
+    }
+
+    /// Paths of the values passing the filter and of every node above them,
+    /// or `None` without a filter
+    fn shown_paths(&self) -> Option<HashSet<DevicePath>> {
+        let filter = self.filter.as_ref()?;
+        let mut shown = HashSet::new();
+        for (path, _) in self.tree.all_devices().filter(|(_, value)| filter(value)) {
+            // Once an ancestor is marked, so are all of its own ancestors
+            let mut next = Some(path.clone());
+            while let Some(path) = next {
+                next = path.parent();
+                if !shown.insert(path) {
+                    break;
+                }
+            }
+        }
+        Some(shown)
+    }
+
+    /// Colorize the bus header (if colors enabled)
+    fn colorize_bus(&self, text: &str) -> String {
+        if !self.style.color.enabled() {
             return text.to_string();
         }
+        text.color(self.style.theme.bus).to_string()
+    }
+
+    /// Colorize a row using the theme (if colors enabled)
+    fn colorize_value(&self, value: &T, depth: usize) -> String {
+        let text = (self.text)(value);
+        if !self.style.color.enabled() {
+            return text;
+        }
 
-        let colored: ColoredString = match depth % 10 {
-            0 => text.red(),
-            1 => text.yellow(),
-            2 => text.green(),
-            3 => text.cyan(),
-            4 => text.blue(),
-            5 => text.magenta(),
-            6 => text.bright_red(),
-            7 => text.bright_yellow(),
-            8 => text.bright_green(),
-            9 => text.bright_cyan(),
-            _ => text.white(),
+        let theme = &self.style.theme;
+        if let Some(ref filter) = self.filter {
+            return if filter(value) {
+                text.color(theme.highlight).bold().to_string()
+            } else {
+                text.dimmed().to_string()
+            };
+        }
+        if self.highlight.as_ref().is_some_and(|matches| matches(value)) {
+            return text.color(theme.highlight).bold().to_string();
+        }
+        let color = match (self.device_of)(value) {
+            Some(device) => theme.device_color(device, depth),
+            None => theme.depth_color(depth),
         };
-        colored.to_string()
-    }
-
-    /// Format a port tree node recursively
-    fn fmt_port_tree(
-        &self,
-        port_tree: &PortTree<String>,
-        prefix: &str,
-        is_last: bool,
-        depth: usize,
-        f: &mut fmt::Formatter<'_>,
-    ) -> fmt::Result {
-        // Print current node if it has a value
-        if let Some(ref key) = port_tree.value {
-            if let Some(device) = self.tree.devices.get(key) {
-                let connector = if depth == 0 {
-                    ""
-                } else if is_last {
-                    self.style.corner
-                } else {
-                    self.style.branch
-                };
-
-                let device_str = self.colorize(&device.to_string(), depth);
-                writeln!(f, "{}{}{}", prefix, connector, device_str)?;
+        text.color(color).to_string()
+    }
+}
+
+/// Visitor that renders a [`TreeFormatter`] row by row
+struct TreeRenderer<'r, 'a, 'f, T> {
+    formatter: &'r TreeFormatter<'a, T>,
+    f: &'r mut fmt::Formatter<'f>,
+    /// Whether each ancestor of the current node was the last child
+    lasts: Vec<bool>,
+    /// Paths left after filtering, see [`TreeFormatter::shown_paths`]
+    shown: Option<HashSet<DevicePath>>,
+    result: fmt::Result,
+}
+
+impl<T> TreeRenderer<'_, '_, '_, T> {
+    fn write_line(&mut self, line: fmt::Arguments<'_>) {
+        if self.result.is_ok() {
+            self.result = writeln!(self.f, "{}", line);
+        }
+    }
+
+    /// Whether the walk starts at bus level, so depth 0 is a bus header
+    fn at_bus_level(&self, node: &VisitNode<'_, T>) -> bool {
+        node.depth == 0 && self.formatter.root.is_none()
+    }
+
+    /// Depth of the rows drawn without a connector: the root of a subtree,
+    /// or the devices on a bus whose header is hidden
+    fn top_depth(&self) -> usize {
+        match self.formatter.root {
+            None if !self.formatter.style.show_header => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl<T> Visitor<T> for TreeRenderer<'_, '_, '_, T> {
+    /// Skip subtrees without any value passing the filter
+    fn should_visit<'v>(&mut self, path: &DevicePath, _values: impl Iterator<Item = &'v T>) -> bool
+    where
+        T: 'v,
+    {
+        self.shown.as_ref().is_none_or(|shown| shown.contains(path))
+    }
+
+    fn enter(&mut self, node: &VisitNode<'_, T>) {
+        let formatter = self.formatter;
+        let style = &formatter.style;
+
+        let top = self.top_depth();
+        if self.at_bus_level(node) {
+            if style.show_header {
+                let bus_label = format!("Bus {:03}", node.path.bus());
+                self.write_line(format_args!("{}", formatter.colorize_bus(&bus_label)));
             }
+        } else if let Some(value) = node.value {
+            let prefix: String = self
+                .lasts
+                .iter()
+                .skip(top + 1)
+                .map(|&last| if last { style.indent.as_str() } else { style.vertical.as_str() })
+                .collect();
+            let connector = if node.depth == top {
+                ""
+            } else if node.is_last {
+                &style.corner
+            } else {
+                &style.branch
+            };
+
+            let row = formatter.colorize_value(value, node.depth);
+            let port = formatter.port_suffix(&node.path);
+            let label = formatter.label_suffix(value);
+            self.write_line(format_args!("{}{}{}{}{}", prefix, connector, row, port, label));
         }
 
-        // Print children
-        let child_ports = port_tree.child_ports();
-        let count = child_ports.len();
+        self.lasts.push(node.is_last);
+    }
 
-        for (i, port) in child_ports.into_iter().enumerate() {
-            if let Some(child) = port_tree.children.get(&port) {
-                let new_prefix = if depth == 0 {
-                    String::new()
-                } else if is_last {
-                    format!("{}{}", prefix, self.style.indent)
-                } else {
-                    format!("{}{}", prefix, self.style.vertical)
-                };
+    fn leave(&mut self, node: &VisitNode<'_, T>) {
+        self.lasts.pop();
+        if self.at_bus_level(node) && self.formatter.style.show_header {
+            self.write_line(format_args!(""));
+        }
+    }
+}
 
-                self.fmt_port_tree(child, &new_prefix, i == count - 1, depth + 1, f)?;
+impl<'a, T> fmt::Display for TreeFormatter<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut renderer = TreeRenderer {
+            formatter: self,
+            f,
+            lasts: Vec::new(),
+            shown: self.shown_paths(),
+            result: Ok(()),
+        };
+        match self.root {
+            Some(ref root) => {
+                self.tree.walk_from(root, &mut renderer);
             }
+            None => self.tree.walk(&mut renderer),
         }
+        renderer.result
+    }
+}
 
-        Ok(())
+/// Output format for the CLI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum OutputFormat {
+    /// Hierarchical tree
+    #[default]
+    Tree,
+    /// Flat list, one device per line
+    List,
+}
+
+impl FromStr for OutputFormat {
+    type Err = FormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(OutputFormat::Tree),
+            "list" => Ok(OutputFormat::List),
+            _ => Err(FormatError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for OutputFormat {
+    type Error = FormatError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
-impl<'a> fmt::Display for TreeFormatter<'a> {
+impl fmt::Display for OutputFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Tree => write!(f, "tree"),
+            OutputFormat::List => write!(f, "list"),
+        }
+    }
+}
 
-        for bus_str in self.tree.buses() {
-            let bus: u8 = bus_str.parse().unwrap_or(0);
+/// A column in list output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Column {
+    /// Device path, e.g. "1:2.3"
+    Path,
+    /// Bus number
+    Bus,
+    /// Device address
+    Address,
+    /// VID:PID pair
+    VidPid,
+    /// Device name
+    Name,
+    /// Manufacturer string
+    Manufacturer,
+    /// Product string
+    Product,
+    /// Serial number
+    Serial,
+    /// Device class
+    Class,
+    /// Connection speed
+    Speed,
+    /// Firmware revision (bcdDevice)
+    Firmware,
+    /// USB specification release (bcdUSB)
+    Usb,
+}
 
-            // Bus level is depth 0
-            let bus_label = format!("Bus {:03}", bus);
-            writeln!(f, "{}", self.colorize(&bus_label, 0))?;
+impl Column {
+    /// All columns in their canonical order
+    pub const ALL: &'static [Column] = &[
+        Column::Path,
+        Column::Bus,
+        Column::Address,
+        Column::VidPid,
+        Column::Name,
+        Column::Manufacturer,
+        Column::Product,
+        Column::Serial,
+        Column::Class,
+        Column::Speed,
+        Column::Firmware,
+        Column::Usb,
+    ];
 
-            if let Some(port_tree) = self.tree.bus_tree(bus_str) {
-                let child_ports = port_tree.child_ports();
-                let count = child_ports.len();
+    /// Default columns for list output
+    pub const DEFAULT: &'static [Column] =
+        &[Column::Path, Column::VidPid, Column::Speed, Column::Name];
 
-                for (i, port) in child_ports.into_iter().enumerate() {
-                    if let Some(child) = port_tree.children.get(&port) {
-                        self.fmt_port_tree(child, "", i == count - 1, 1, f)?;
-                    }
-                }
+    /// Column name as used in config files and on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Path => "path",
+            Column::Bus => "bus",
+            Column::Address => "address",
+            Column::VidPid => "vidpid",
+            Column::Name => "name",
+            Column::Manufacturer => "manufacturer",
+            Column::Product => "product",
+            Column::Serial => "serial",
+            Column::Class => "class",
+            Column::Speed => "speed",
+            Column::Firmware => "firmware",
+            Column::Usb => "usb",
+        }
+    }
+
+    /// Render this column's value for a device
+    pub fn value(self, device: &UsbDevice) -> String {
+        match self {
+            Column::Path => device.path_key(),
+            Column::Bus => format!("{:03}", device.bus),
+            Column::Address => format!("{:03}", device.address),
+            Column::VidPid => device.vid_pid(),
+            Column::Name => device.name.clone(),
+            Column::Manufacturer => device.manufacturer.clone().unwrap_or_default(),
+            Column::Product => device.product.clone().unwrap_or_default(),
+            Column::Serial => device.serial.clone().unwrap_or_default(),
+            Column::Class => class_name(device.class)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("0x{:02x}", device.class)),
+            Column::Speed => device.speed.map(speed_name).unwrap_or("").to_string(),
+            Column::Firmware => device.firmware_version(),
+            Column::Usb => device.usb_version(),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = FormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Column::ALL
+            .iter()
+            .copied()
+            .find(|c| c.name() == s)
+            .ok_or_else(|| FormatError::UnknownColumn(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for Column {
+    type Error = FormatError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Formatter for rendering devices as an aligned flat list
+///
+/// # Examples
+///
+/// ```no_run
+/// use usbinfo::{usb_tree, Column, ListFormatter};
+///
+/// let tree = usb_tree().unwrap();
+/// let formatter = ListFormatter::new(&tree)
+///     .with_columns(vec![Column::Path, Column::VidPid, Column::Serial]);
+/// println!("{}", formatter);
+/// ```
+pub struct ListFormatter<'a> {
+    tree: &'a UsbTree<UsbDevice>,
+    columns: Vec<Column>,
+    show_header: bool,
+    filter: Option<DevicePredicate<'a>>,
+}
+
+impl<'a> ListFormatter<'a> {
+    /// Create a list formatter with the default columns
+    pub fn new(tree: &'a UsbTree<UsbDevice>) -> Self {
+        Self {
+            tree,
+            columns: Column::DEFAULT.to_vec(),
+            show_header: true,
+            filter: None,
+        }
+    }
+
+    /// Set the columns to display
+    pub fn with_columns(mut self, columns: Vec<Column>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Set whether to print a header row
+    pub fn with_header(mut self, show_header: bool) -> Self {
+        self.show_header = show_header;
+        self
+    }
+
+    /// Only list devices matching the predicate
+    pub fn with_filter(mut self, predicate: impl Fn(&UsbDevice) -> bool + 'a) -> Self {
+        self.filter = Some(Box::new(predicate));
+        self
+    }
+}
+
+impl<'a> fmt::Display for ListFormatter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let devices: Vec<&UsbDevice> = self
+            .tree
+            .dfs()
+            .map(|(_, _, device)| device)
+            .filter(|device| self.filter.as_ref().is_none_or(|matches| matches(device)))
+            .collect();
+
+        let mut rows: Vec<Vec<String>> = Vec::with_capacity(devices.len() + 1);
+        if self.show_header {
+            rows.push(self.columns.iter().map(|c| c.name().to_uppercase()).collect());
+        }
+        rows.extend(
+            devices
+                .iter()
+                .map(|device| self.columns.iter().map(|c| c.value(device)).collect()),
+        );
+
+        let mut widths = vec![0; self.columns.len()];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
             }
+        }
 
-            writeln!(f)?;
+        for row in &rows {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:<width$}", cell))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, device};
+
+    /// A device named after its ports, addressed by its depth
+    fn numbered(vid: u16, path: &str) -> UsbDevice {
+        let ports = path.parse::<DevicePath>().unwrap().ports().to_vec();
+        device(path)
+            .with_id(vid, 0x0001)
+            .with_address(ports.len() as u8 + 1)
+            .with_name(&format!("Dev {:?}", ports))
+            .build()
+    }
+
+    fn tree() -> UsbTree<UsbDevice> {
+        test_support::tree([
+            numbered(0x1111, "1:1"),
+            numbered(0x1111, "1:1.1"),
+            numbered(0x2222, "1:1.2"),
+            numbered(0x1111, "1:2"),
+        ])
+    }
+
+    #[test]
+    fn test_filter_keeps_ancestors() {
+        let tree = tree();
+        let output = TreeFormatter::plain(&tree)
+            .with_filter(|dev| dev.vid == 0x2222)
+            .to_string();
+        let expected = [
+            "Bus 001",
+            "└── Device 002: ID 1111:0001 Dev [1]",
+            "    └── Device 003: ID 2222:0001 Dev [1, 2]",
+            "",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_filter_skips_empty_buses() {
+        let tree = tree();
+        let output = TreeFormatter::plain(&tree).with_filter(|_| false).to_string();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_without_bus_headers() {
+        let tree = tree();
+        let output = TreeFormatter::with_style(&tree, TreeStyle::plain().with_header(false)).to_string();
+        let expected = [
+            "Device 002: ID 1111:0001 Dev [1]",
+            "├── Device 003: ID 1111:0001 Dev [1, 1]",
+            "└── Device 003: ID 2222:0001 Dev [1, 2]",
+            "Device 002: ID 1111:0001 Dev [2]",
+            "",
+        ];
+        assert_eq!(output, expected.join("\n"));
+    }
+
+    #[test]
+    fn test_from_fn_on_mapped_tree() {
+        let vids = tree().map(|dev| dev.vid);
+        let output = TreeFormatter::from_fn(&vids, TreeStyle::plain(), |vid| format!("{:04x}", vid))
+            .with_filter(|&vid| vid == 0x2222)
+            .to_string();
+        assert_eq!(output, "Bus 001\n└── 1111\n    └── 2222\n\n");
+    }
+
+    #[test]
+    fn test_buses_and_ports_in_numeric_order() {
+        let tree = test_support::tree(["10:1", "2:10", "2:9"].map(|path| numbered(0x1111, path)));
+
+        let output = TreeFormatter::plain(&tree).to_string();
+        let buses: Vec<&str> = output.lines().filter(|l| l.starts_with("Bus")).collect();
+        assert_eq!(buses, ["Bus 002", "Bus 010"]);
+        assert!(output.find("Dev [9]").unwrap() < output.find("Dev [10]").unwrap());
+
+        let list = ListFormatter::new(&tree)
+            .with_columns(vec![Column::Path])
+            .with_header(false)
+            .to_string();
+        assert_eq!(list, "2:9\n2:10\n10:1\n");
+    }
+}
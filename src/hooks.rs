@@ -0,0 +1,221 @@
+//! Commands run when devices attach or detach
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::events::{EventKind, EventRecord};
+
+/// A command to run for matching attach or detach events
+///
+/// Hooks are read from `[[hook]]` entries in the config file:
+///
+/// ```
+/// use std::time::Duration;
+/// use usbinfo::{Config, EventKind};
+///
+/// let config: Config = r#"
+///     [[hook]]
+///     on = "attach"
+///     vid_pid = ["2e8a:0003"]
+///     run = ["picotool", "load", "firmware.uf2"]
+///     debounce = "500ms"
+/// "#.parse().unwrap();
+///
+/// let hook = &config.hooks[0];
+/// assert_eq!(hook.on, Some(EventKind::Attach));
+/// assert_eq!(hook.debounce, Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hook {
+    /// Only run on attach or on detach; both if unset
+    pub on: Option<EventKind>,
+    /// VID:PID patterns, see [`VidPidPattern`](crate::VidPidPattern)
+    pub vid_pid: Option<Vec<String>>,
+    /// Filter expression, see [`Filter`](crate::Filter)
+    #[serde(rename = "where")]
+    pub filter: Option<String>,
+    /// Program and arguments to run; config files must give at least the program
+    pub run: Vec<String>,
+    /// How long a path must stay quiet before the hook runs
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub debounce: Duration,
+}
+
+impl Hook {
+    /// Check whether the hook runs for this kind of event
+    pub fn triggers_on(&self, kind: EventKind) -> bool {
+        self.on.is_none_or(|on| on == kind)
+    }
+
+    /// Build the command for an event, with the device in its environment
+    ///
+    /// Returns `None` if the hook has nothing to run.
+    pub fn command(&self, record: &EventRecord, label: Option<&str>) -> Option<Command> {
+        let (program, args) = self.run.split_first()?;
+        let mut command = Command::new(program);
+        command.args(args).envs(hook_env(record, label));
+        Some(command)
+    }
+}
+
+/// Environment variables describing the device of an event
+///
+/// Fields the device does not have are set to an empty string, so hooks
+/// never see values left over from the calling environment.
+pub fn hook_env(record: &EventRecord, label: Option<&str>) -> Vec<(&'static str, String)> {
+    let device = &record.device;
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+    let event = match record.kind {
+        EventKind::Attach => "attach",
+        EventKind::Detach => "detach",
+    };
+    vec![
+        ("USBINFO_EVENT", event.to_string()),
+        ("USBINFO_PATH", record.path.to_string()),
+        ("USBINFO_VIDPID", device.vid_pid()),
+        ("USBINFO_VID", format!("{:04x}", device.vid)),
+        ("USBINFO_PID", format!("{:04x}", device.pid)),
+        ("USBINFO_SERIAL", text(&device.serial)),
+        ("USBINFO_MANUFACTURER", text(&device.manufacturer)),
+        ("USBINFO_PRODUCT", text(&device.product)),
+        ("USBINFO_BUS", device.bus.to_string()),
+        ("USBINFO_ADDRESS", device.address.to_string()),
+        ("USBINFO_LABEL", label.unwrap_or_default().to_string()),
+        (
+            "USBINFO_SYSFS_PATH",
+            device
+                .sysfs_path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Holds back items until their key has been quiet for a while
+///
+/// Pushing an item for a key that already has one pending replaces it and
+/// restarts its delay, so a burst of events for one device yields only the
+/// last of them.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use usbinfo::Debouncer;
+///
+/// let start = Instant::now();
+/// let mut debouncer = Debouncer::new();
+/// debouncer.push("1:2", "attach", start + Duration::from_millis(500));
+/// debouncer.push("1:2", "detach", start + Duration::from_millis(700));
+///
+/// assert!(debouncer.ready(start + Duration::from_millis(600)).is_empty());
+/// assert_eq!(debouncer.ready(start + Duration::from_millis(700)), ["detach"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Debouncer<K, T> {
+    pending: BTreeMap<K, (Instant, T)>,
+}
+
+impl<K: Ord, T> Default for Debouncer<K, T> {
+    fn default() -> Self {
+        Self {
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, T> Debouncer<K, T> {
+    /// Create an empty debouncer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold back `item` until `due`, replacing anything pending for `key`
+    pub fn push(&mut self, key: K, item: T, due: Instant) {
+        self.pending.insert(key, (due, item));
+    }
+
+    /// When the next pending item becomes ready
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.values().map(|(due, _)| *due).min()
+    }
+
+    /// Take every item that is due at `now`, earliest first
+    pub fn ready(&mut self, now: Instant) -> Vec<T> {
+        let (ready, pending) = mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(_, (due, _))| *due <= now);
+        self.pending = pending;
+        Self { pending: ready }.drain()
+    }
+
+    /// Take every pending item, due or not, earliest first
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut pending: Vec<(Instant, T)> = mem::take(&mut self.pending).into_values().collect();
+        pending.sort_by_key(|(due, _)| *due);
+        pending.into_iter().map(|(_, item)| item).collect()
+    }
+
+    /// Check whether nothing is pending
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::test_support::device;
+
+    #[test]
+    fn test_hook_command_env() {
+        let record = EventRecord {
+            timestamp: SystemTime::now(),
+            kind: EventKind::Detach,
+            path: "1:2.3".parse().unwrap(),
+            device: device("1:2.3").with_id(0x2e8a, 0x0003).with_serial("E660").build(),
+        };
+        let hook = Hook {
+            on: Some(EventKind::Attach),
+            run: vec!["true".to_string()],
+            ..Default::default()
+        };
+        assert!(!hook.triggers_on(record.kind));
+
+        let command = hook.command(&record, Some("pico")).unwrap();
+        let keys = ["USBINFO_PATH", "USBINFO_VIDPID", "USBINFO_SERIAL", "USBINFO_LABEL", "USBINFO_PRODUCT"];
+        let env: Vec<(String, String)> = command
+            .get_envs()
+            .filter(|(key, _)| keys.contains(&key.to_str().unwrap()))
+            .map(|(key, value)| {
+                let value = value.unwrap().to_string_lossy().into_owned();
+                (key.to_string_lossy().into_owned(), value)
+            })
+            .collect();
+        let expected = [
+            ("USBINFO_PATH", "1:2.3"),
+            ("USBINFO_VIDPID", "2e8a:0003"),
+            ("USBINFO_SERIAL", "E660"),
+            ("USBINFO_PRODUCT", ""),
+            ("USBINFO_LABEL", "pico"),
+        ];
+        let mut expected: Vec<(String, String)> =
+            expected.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        expected.sort();
+        assert_eq!(env, expected);
+        assert!(Hook::default().command(&record, None).is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Secondary indexes for looking devices up without scanning the tree
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::device::UsbDevice;
+use crate::path::DevicePath;
+
+/// Values that can be indexed by serial, VID:PID, bus:address and text
+pub trait Indexable {
+    /// Get the keys to index this value under
+    fn index_keys(&self) -> IndexKeys;
+}
+
+/// Keys a value is indexed under
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexKeys {
+    /// Serial number
+    pub serial: Option<String>,
+    /// Vendor and product ID
+    pub vid_pid: (u16, u16),
+    /// Bus number and device address
+    pub bus_address: (u8, u8),
+    /// Free text for [`search`](crate::UsbTree::search), e.g. manufacturer and product
+    pub text: String,
+}
+
+impl Indexable for UsbDevice {
+    fn index_keys(&self) -> IndexKeys {
+        let text = [&self.manufacturer, &self.product]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+        IndexKeys {
+            serial: self.serial.clone(),
+            vid_pid: (self.vid, self.pid),
+            bus_address: (self.bus, self.address),
+            text,
+        }
+    }
+}
+
+/// Lookup tables from index keys to device paths
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DeviceIndex {
+    by_serial: HashMap<String, BTreeSet<DevicePath>>,
+    by_vid_pid: HashMap<(u16, u16), BTreeSet<DevicePath>>,
+    by_bus_address: HashMap<(u8, u8), DevicePath>,
+    keys: HashMap<DevicePath, IndexKeys>,
+}
+
+impl DeviceIndex {
+    /// Index a value at `path`, replacing what was indexed there before
+    pub(crate) fn insert(&mut self, path: &DevicePath, keys: IndexKeys) {
+        self.remove(path);
+        if let Some(ref serial) = keys.serial {
+            self.by_serial.entry(serial.clone()).or_default().insert(path.clone());
+        }
+        self.by_vid_pid.entry(keys.vid_pid).or_default().insert(path.clone());
+        self.by_bus_address.insert(keys.bus_address, path.clone());
+        self.keys.insert(path.clone(), IndexKeys {
+            text: keys.text.to_lowercase(),
+            ..keys
+        });
+    }
+
+    /// Drop everything indexed at `path`
+    pub(crate) fn remove(&mut self, path: &DevicePath) {
+        let Some(keys) = self.keys.remove(path) else {
+            return;
+        };
+        if let Some(serial) = keys.serial {
+            remove_from(&mut self.by_serial, serial, path);
+        }
+        remove_from(&mut self.by_vid_pid, keys.vid_pid, path);
+        if self.by_bus_address.get(&keys.bus_address) == Some(path) {
+            self.by_bus_address.remove(&keys.bus_address);
+        }
+    }
+
+    pub(crate) fn serial(&self, serial: &str) -> Option<&DevicePath> {
+        self.by_serial.get(serial)?.first()
+    }
+
+    pub(crate) fn vid_pid(&self, vid: u16, pid: u16) -> impl Iterator<Item = &DevicePath> {
+        self.by_vid_pid.get(&(vid, pid)).into_iter().flatten()
+    }
+
+    pub(crate) fn bus_address(&self, bus: u8, address: u8) -> Option<&DevicePath> {
+        self.by_bus_address.get(&(bus, address))
+    }
+
+    /// Paths whose text contains every word of `query`, ignoring case
+    pub(crate) fn search(&self, query: &str) -> BTreeSet<&DevicePath> {
+        self.keys
+            .iter()
+            .filter(|(_, keys)| text_matches(&keys.text, query))
+            .map(|(path, _)| path)
+            .collect()
+    }
+}
+
+/// Check whether lowercase `text` contains every word of `query`
+pub(crate) fn text_matches(text: &str, query: &str) -> bool {
+    query
+        .split_whitespace()
+        .all(|word| text.contains(&word.to_lowercase()))
+}
+
+fn remove_from<K: std::hash::Hash + Eq>(
+    map: &mut HashMap<K, BTreeSet<DevicePath>>,
+    key: K,
+    path: &DevicePath,
+) {
+    if let Some(paths) = map.get_mut(&key) {
+        paths.remove(path);
+        if paths.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Human-readable device labels
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::config::config_dir;
+use crate::device::{parse_vid_pid, UsbDevice};
+use crate::error::{DevicePathError, LabelError};
+use crate::path::{DevicePath, DevicePathPattern};
+use crate::tree::UsbTree;
+
+/// What a label refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelTarget {
+    /// Any device with this serial number
+    Serial(String),
+    /// A device with this VID:PID and serial number
+    VidPidSerial {
+        /// Vendor ID
+        vid: u16,
+        /// Product ID
+        pid: u16,
+        /// Serial number
+        serial: String,
+    },
+    /// Whatever device is plugged in at this path
+    Path(DevicePath),
+}
+
+impl LabelTarget {
+    /// Check whether a device is the target of this label
+    pub fn matches(&self, device: &UsbDevice) -> bool {
+        match self {
+            LabelTarget::Serial(serial) => device.serial.as_deref() == Some(serial.as_str()),
+            LabelTarget::VidPidSerial { vid, pid, serial } => {
+                device.vid == *vid
+                    && device.pid == *pid
+                    && device.serial.as_deref() == Some(serial.as_str())
+            }
+            LabelTarget::Path(path) => device.bus == path.bus() && device.port_path == path.ports(),
+        }
+    }
+
+    /// Lower ranks win when several labels match the same device
+    fn rank(&self) -> u8 {
+        match self {
+            LabelTarget::VidPidSerial { .. } => 0,
+            LabelTarget::Serial(_) => 1,
+            LabelTarget::Path(_) => 2,
+        }
+    }
+}
+
+/// On-disk label entry
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LabelEntry {
+    serial: Option<String>,
+    vid_pid: Option<String>,
+    path: Option<String>,
+}
+
+impl LabelEntry {
+    fn into_target(self, name: &str) -> Result<LabelTarget, LabelError> {
+        let invalid = |reason: &str| LabelError::InvalidEntry {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        };
+        match (self.serial, self.vid_pid, self.path) {
+            (Some(serial), None, None) => Ok(LabelTarget::Serial(serial)),
+            (Some(serial), Some(vid_pid), None) => {
+                let (vid, pid) =
+                    parse_vid_pid(&vid_pid).ok_or_else(|| invalid("invalid VID:PID"))?;
+                Ok(LabelTarget::VidPidSerial { vid, pid, serial })
+            }
+            (None, None, Some(path)) => Ok(LabelTarget::Path(
+                path.parse().map_err(|_| invalid("invalid device path"))?,
+            )),
+            (None, Some(_), None) => Err(invalid("vid_pid requires a serial")),
+            _ => Err(invalid("expected serial, vid_pid + serial, or path")),
+        }
+    }
+}
+
+/// A set of device labels keyed by name
+///
+/// Label names can be used in place of a device path by prefixing them
+/// with `@`, e.g. `usbinfo show @dut-relay`.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{LabelTarget, Labels};
+///
+/// let labels: Labels = r#"
+///     "bench-3 oscilloscope" = { serial = "MY51230042" }
+///     "dut-relay" = { vid_pid = "16c0:05df", serial = "A1B2" }
+///     "front-hub" = { path = "1:2" }
+/// "#.parse().unwrap();
+///
+/// assert_eq!(labels.len(), 3);
+/// assert!(matches!(labels.target("front-hub"), Some(LabelTarget::Path(_))));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    entries: BTreeMap<String, LabelTarget>,
+}
+
+impl Labels {
+    /// Create an empty label set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Default location of the labels file
+    pub fn default_path() -> Option<std::path::PathBuf> {
+        config_dir().map(|dir| dir.join("labels.toml"))
+    }
+
+    /// Load labels from the default location, or an empty set if there is none
+    pub fn load() -> Result<Self, LabelError> {
+        match Self::default_path().map(Self::from_file) {
+            Some(Err(LabelError::Io(e))) if e.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Some(result) => result,
+            None => Ok(Self::new()),
+        }
+    }
+
+    /// Load labels from a TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LabelError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Add or replace a label
+    pub fn insert(&mut self, name: impl Into<String>, target: LabelTarget) {
+        self.entries.insert(name.into(), target);
+    }
+
+    /// Get what a label refers to
+    pub fn target(&self, name: &str) -> Option<&LabelTarget> {
+        self.entries.get(name)
+    }
+
+    /// Get the label for a device, preferring the most specific match
+    pub fn label_for(&self, device: &UsbDevice) -> Option<&str> {
+        self.entries
+            .iter()
+            .filter(|(_, target)| target.matches(device))
+            .min_by_key(|(_, target)| target.rank())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Find the path of the device a label refers to
+    pub fn resolve(&self, name: &str, tree: &UsbTree<UsbDevice>) -> Result<DevicePath, LabelError> {
+        let target = self
+            .target(name)
+            .ok_or_else(|| LabelError::UnknownLabel(name.to_string()))?;
+        let found = match target {
+            LabelTarget::Path(path) => return Ok(path.clone()),
+            LabelTarget::Serial(serial) => tree.find_by_serial(serial),
+            LabelTarget::VidPidSerial { vid, pid, .. } => tree
+                .find_by_vid_pid(*vid, *pid)
+                .into_iter()
+                .find(|(_, device)| target.matches(device)),
+        };
+        found
+            .map(|(path, _)| path)
+            .ok_or_else(|| LabelError::NotConnected(name.to_string()))
+    }
+
+    /// Resolve a path argument that is either `@label` or any notation
+    /// accepted by [`DevicePath::resolve`]
+    pub fn resolve_path(&self, spec: &str, tree: &UsbTree<UsbDevice>) -> Result<DevicePath, LabelError> {
+        match spec.strip_prefix('@') {
+            Some(name) => self.resolve(name, tree),
+            None => Ok(DevicePath::resolve(spec, tree)?),
+        }
+    }
+
+    /// Iterate over all labels in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &LabelTarget)> {
+        self.entries.iter().map(|(name, target)| (name.as_str(), target))
+    }
+
+    /// Number of labels
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromStr for Labels {
+    type Err = LabelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw: HashMap<String, LabelEntry> = toml::from_str(s)?;
+        let mut labels = Labels::new();
+        for (name, entry) in raw {
+            let target = entry.into_target(&name)?;
+            labels.insert(name, target);
+        }
+        Ok(labels)
+    }
+}
+
+/// Where a device must be: a path pattern or a `@label`
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{DeviceLocation, LabelTarget, Labels, UsbDevice};
+///
+/// let mut labels = Labels::new();
+/// labels.insert("dut", LabelTarget::Serial("A1B2C3".to_string()));
+///
+/// let dut = UsbDevice { bus: 1, port_path: vec![4], serial: Some("A1B2C3".to_string()), ..Default::default() };
+/// let location: DeviceLocation = "@dut".parse().unwrap();
+/// assert!(location.matches(&dut.path(), &dut, &labels));
+/// assert!(!"1:2.*".parse::<DeviceLocation>().unwrap().matches(&dut.path(), &dut, &labels));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DeviceLocation {
+    /// Any device at a path matching the pattern
+    Pattern(DevicePathPattern),
+    /// The device a label refers to, by name without the `@`
+    Label(String),
+}
+
+impl DeviceLocation {
+    /// Check whether a device at `path` is at this location
+    ///
+    /// Unknown labels match nothing; see [`DeviceLocation::validate`].
+    pub fn matches(&self, path: &DevicePath, device: &UsbDevice, labels: &Labels) -> bool {
+        match self {
+            DeviceLocation::Pattern(pattern) => pattern.matches(path),
+            DeviceLocation::Label(name) => labels.target(name).is_some_and(|t| t.matches(device)),
+        }
+    }
+
+    /// Check whether whatever is plugged in at `path` is at this location
+    ///
+    /// True for patterns matching `path` and labels of that path; labels of
+    /// serial numbers depend on the device, not its path.
+    pub fn matches_path(&self, path: &DevicePath, labels: &Labels) -> bool {
+        match self {
+            DeviceLocation::Pattern(pattern) => pattern.matches(path),
+            DeviceLocation::Label(name) => {
+                matches!(labels.target(name), Some(LabelTarget::Path(target)) if target == path)
+            }
+        }
+    }
+
+    /// Check that a label location refers to a known label
+    pub fn validate(&self, labels: &Labels) -> Result<(), LabelError> {
+        match self {
+            DeviceLocation::Label(name) if labels.target(name).is_none() => {
+                Err(LabelError::UnknownLabel(name.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for DeviceLocation {
+    type Err = DevicePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            Some(name) => Ok(DeviceLocation::Label(name.to_string())),
+            None => Ok(DeviceLocation::Pattern(s.parse()?)),
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceLocation {
+    type Error = DevicePathError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for DeviceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceLocation::Pattern(pattern) => write!(f, "{}", pattern),
+            DeviceLocation::Label(name) => write!(f, "@{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, device, DeviceBuilder};
+
+    fn relay(path: &str) -> DeviceBuilder {
+        device(path).with_id(0x16c0, 0x05df).with_address(7).with_name("Relay")
+    }
+
+    #[test]
+    fn test_most_specific_label_wins() {
+        let labels: Labels = r#"
+            "port-4" = { path = "1:2.4" }
+            "relay" = { serial = "A1B2" }
+            "dut-relay" = { vid_pid = "16c0:05df", serial = "A1B2" }
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(labels.label_for(&relay("1:2.4").with_serial("A1B2").build()), Some("dut-relay"));
+        assert_eq!(labels.label_for(&relay("1:2.4").build()), Some("port-4"));
+        assert_eq!(labels.label_for(&relay("1:2.5").build()), None);
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let tree = test_support::tree([relay("1:2.4.1").with_serial("A1B2").build()]);
+
+        let mut labels = Labels::new();
+        labels.insert("dut-relay", LabelTarget::Serial("A1B2".to_string()));
+
+        assert_eq!(
+            labels.resolve_path("@dut-relay", &tree).unwrap(),
+            DevicePath::new(1, vec![2, 4, 1])
+        );
+        assert_eq!(
+            labels.resolve_path("1:3", &tree).unwrap(),
+            DevicePath::new(1, vec![3])
+        );
+        assert!(matches!(
+            labels.resolve_path("@nope", &tree),
+            Err(LabelError::UnknownLabel(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_entry() {
+        let err = r#""x" = { vid_pid = "16c0:05df" }"#.parse::<Labels>().unwrap_err();
+        assert!(matches!(err, LabelError::InvalidEntry { .. }));
+    }
+}
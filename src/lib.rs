@@ -26,16 +26,71 @@
 //!     println!("  - {}", dev);
 //! }
 //! ```
+//!
+//! Inside an async service, use [`usb_tree_async`] instead so enumeration
+//! does not block the executor:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), usbinfo::UsbTreeError> {
+//! let tree = usbinfo::usb_tree_async().await?;
+//! if let Some((path, _)) = tree.find_by_serial("A1B2C3") {
+//!     println!("DUT is at {}", path);
+//! }
+//! # Ok(())
+//! # }
+//! ```
 
+mod config;
 mod device;
 mod error;
+mod events;
+mod filter;
+mod flapping;
 mod formatter;
+mod hooks;
+mod index;
+mod labels;
+mod live;
+mod manifest;
+mod notation;
 mod path;
+mod ports;
+mod recording;
+mod source;
+#[cfg(test)]
+mod test_support;
+mod theme;
 mod tree;
+mod vidpid;
+mod visit;
+mod wait;
 
 // Re-export public API
-pub use device::{matches_vid_pid, UsbDevice};
-pub use error::{DevicePathError, UsbTreeError};
-pub use formatter::{TreeFormatter, TreeStyle};
-pub use path::DevicePath;
+pub use config::{config_dir, Config, Settings, CONFIG_ENV};
+pub use device::{class_name, matches_vid_pid, parse_vid_pid, speed_name, UsbDevice};
+pub use error::{
+    ConfigError, DevicePathError, FilterError, FilterErrorKind, FormatError, LabelError,
+    ManifestError, PortMapError, RecordingError, ThemeError, UsbTreeError, VidPidError,
+};
+pub use events::{EventKind, EventRecord};
+pub use filter::Filter;
+pub use flapping::{
+    ConnectionStats, DeviceIdentity, DeviceReport, FlapReport, FlapTarget, FlapThreshold,
+    FlapTracker, PortReport,
+};
+pub use formatter::{Column, ListFormatter, OutputFormat, TreeFormatter, TreeLabel, TreeStyle};
+pub use hooks::{hook_env, Debouncer, Hook};
+pub use index::{IndexKeys, Indexable};
+pub use labels::{DeviceLocation, LabelTarget, Labels};
+pub use live::{EventSource, LiveTree, Subscription, SystemEvents, TreeChange, TreeEvent};
+pub use manifest::{CheckResult, Drift, ExpectedDevice, Manifest, ManifestReport};
+pub use notation::{InterfacePath, LsusbAddress, UdevIdPath};
+pub use path::{DevicePath, DevicePathPattern};
+pub use ports::{hostname, PortMap};
+pub use recording::{Recorder, Replay};
+pub use source::{usb_tree_async, DeviceSource, StaticSource, SystemSource};
+pub use theme::{ColorBy, ColorMode, Theme};
 pub use tree::{usb_tree, PortTree, UsbTree};
+pub use vidpid::{IdMatch, VidPidFilter, VidPidPattern};
+pub use visit::{Order, Traverse, VisitNode, Visitor};
+pub use wait::{DeviceWait, WaitOutcome};
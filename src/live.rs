@@ -0,0 +1,479 @@
+//! A device tree kept up to date from hotplug events
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use futures_lite::{Stream, StreamExt};
+use nusb::hotplug::{HotplugEvent, HotplugWatch};
+use nusb::DeviceId;
+
+use crate::device::UsbDevice;
+use crate::error::UsbTreeError;
+use crate::path::DevicePath;
+use crate::source::system_devices;
+use crate::tree::UsbTree;
+
+/// A raw hotplug event to apply to a tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeEvent {
+    /// A device was connected
+    Attached(UsbDevice),
+    /// The device at a path was disconnected
+    Detached {
+        /// Where the device was
+        path: DevicePath,
+        /// Its bus address, if known; a device at the path with another
+        /// address has replaced it and is left alone
+        address: Option<u8>,
+    },
+}
+
+/// A change to a [`LiveTree`], as seen by subscribers
+// Changed carries both devices by value, like the other variants carry one
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeChange {
+    /// A device appeared
+    Added {
+        /// Where the device is
+        path: DevicePath,
+        /// The new device
+        device: UsbDevice,
+    },
+    /// A device went away
+    Removed {
+        /// Where the device was
+        path: DevicePath,
+        /// The device as it was last seen
+        device: UsbDevice,
+    },
+    /// A different device, or the same one re-enumerated, took a path
+    Changed {
+        /// Path of the device
+        path: DevicePath,
+        /// The device previously at the path
+        old: UsbDevice,
+        /// The device now at the path
+        new: UsbDevice,
+    },
+}
+
+impl TreeChange {
+    /// Path of the device that changed
+    pub fn path(&self) -> &DevicePath {
+        match self {
+            TreeChange::Added { path, .. }
+            | TreeChange::Removed { path, .. }
+            | TreeChange::Changed { path, .. } => path,
+        }
+    }
+
+    /// The device after the change, or before it for removals
+    pub fn device(&self) -> &UsbDevice {
+        match self {
+            TreeChange::Added { device, .. } | TreeChange::Removed { device, .. } => device,
+            TreeChange::Changed { new, .. } => new,
+        }
+    }
+
+    /// Narrow the change to the devices matching `predicate`
+    ///
+    /// A `Changed` path where only one side matches becomes the removal of
+    /// the old device or the addition of the new one, so a filtered stream
+    /// still sees every matching device come and go.
+    pub fn filter(self, predicate: impl Fn(&UsbDevice) -> bool) -> Option<TreeChange> {
+        match self {
+            TreeChange::Added { ref device, .. } | TreeChange::Removed { ref device, .. } => {
+                predicate(device).then_some(self)
+            }
+            TreeChange::Changed { path, old, new } => match (predicate(&old), predicate(&new)) {
+                (true, true) => Some(TreeChange::Changed { path, old, new }),
+                (true, false) => Some(TreeChange::Removed { path, device: old }),
+                (false, true) => Some(TreeChange::Added { path, device: new }),
+                (false, false) => None,
+            },
+        }
+    }
+}
+
+/// Somewhere to receive hotplug events from
+///
+/// [`SystemEvents`] watches this machine; other sources can feed
+/// synthetic or recorded events into a [`LiveTree`].
+pub trait EventSource {
+    /// Get the devices connected when watching starts
+    fn snapshot(&mut self) -> impl Future<Output = Result<Vec<UsbDevice>, UsbTreeError>> + Send;
+
+    /// Wait for the next event, or `None` when the source is exhausted
+    fn next_event(&mut self) -> impl Future<Output = Option<TreeEvent>> + Send;
+
+    /// When the last event happened
+    ///
+    /// The current time, unless the source replays events from the past.
+    fn timestamp(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    /// How many times as fast as real time events arrive
+    ///
+    /// Timeouts are divided by it, so they expire at the same recorded time.
+    fn speed(&self) -> f64 {
+        1.0
+    }
+}
+
+/// Hotplug events from this machine, via nusb
+///
+/// nusb reports disconnects by device ID only, so the path of every
+/// connected device is remembered to translate them.
+pub struct SystemEvents {
+    watch: HotplugWatch,
+    paths: HashMap<DeviceId, (DevicePath, u8)>,
+}
+
+impl SystemEvents {
+    /// Start watching for hotplug events
+    ///
+    /// Watching starts before the snapshot is taken, so no event between
+    /// the two is lost.
+    pub fn new() -> Result<Self, UsbTreeError> {
+        let watch = nusb::watch_devices().map_err(|e| UsbTreeError::ListDevices(e.to_string()))?;
+        Ok(Self {
+            watch,
+            paths: HashMap::new(),
+        })
+    }
+}
+
+impl EventSource for SystemEvents {
+    async fn snapshot(&mut self) -> Result<Vec<UsbDevice>, UsbTreeError> {
+        let devices = system_devices().await?;
+        for (id, device) in &devices {
+            self.paths.insert(*id, (device.path(), device.address));
+        }
+        Ok(devices.into_iter().map(|(_, device)| device).collect())
+    }
+
+    async fn next_event(&mut self) -> Option<TreeEvent> {
+        loop {
+            match self.watch.next().await? {
+                HotplugEvent::Connected(info) => {
+                    let id = info.id();
+                    let device = blocking::unblock(move || UsbDevice::from_device_info(&info)).await;
+                    self.paths.insert(id, (device.path(), device.address));
+                    return Some(TreeEvent::Attached(device));
+                }
+                HotplugEvent::Disconnected(id) => {
+                    // Devices we never saw connect are ignored
+                    if let Some((path, address)) = self.paths.remove(&id) {
+                        let address = Some(address);
+                        return Some(TreeEvent::Detached { path, address });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A stream of changes to a [`LiveTree`]
+///
+/// Changes are buffered until received. Dropping the subscription
+/// unsubscribes.
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: Pin<Box<async_channel::Receiver<TreeChange>>>,
+}
+
+impl Subscription {
+    /// Wait for the next change; `None` once the tree is gone
+    pub async fn recv(&self) -> Option<TreeChange> {
+        self.receiver.recv().await.ok()
+    }
+
+    /// Block the current thread until the next change
+    pub fn recv_blocking(&self) -> Option<TreeChange> {
+        self.receiver.recv_blocking().ok()
+    }
+
+    /// Get the next change if one is waiting
+    pub fn try_recv(&self) -> Option<TreeChange> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = TreeChange;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.as_mut().poll_next(cx)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    tree: RwLock<UsbTree<UsbDevice>>,
+    subscribers: Mutex<Vec<async_channel::Sender<TreeChange>>>,
+}
+
+/// A `UsbTree` that follows hotplug events
+///
+/// Cloning a `LiveTree` gives another handle to the same tree, so one
+/// task can run [`LiveTree::run`] while others read and subscribe.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{LiveTree, TreeChange, TreeEvent, UsbDevice};
+///
+/// let live = LiveTree::new();
+/// let changes = live.subscribe();
+///
+/// let device = UsbDevice { bus: 1, port_path: vec![2], ..Default::default() };
+/// live.apply(TreeEvent::Attached(device));
+///
+/// assert!(matches!(changes.try_recv(), Some(TreeChange::Added { .. })));
+/// assert_eq!(live.read().len(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LiveTree {
+    shared: Arc<Shared>,
+}
+
+impl LiveTree {
+    /// Create an empty live tree
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a live tree starting from an existing tree
+    pub fn from_tree(tree: UsbTree<UsbDevice>) -> Self {
+        let live = Self::new();
+        *live.write() = tree;
+        live
+    }
+
+    /// Lock the tree for reading
+    ///
+    /// Events are not applied while the guard is held, so keep it short.
+    pub fn read(&self) -> RwLockReadGuard<'_, UsbTree<UsbDevice>> {
+        self.shared.tree.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Copy the current tree
+    pub fn snapshot(&self) -> UsbTree<UsbDevice> {
+        self.read().clone()
+    }
+
+    /// Receive every change applied from now on
+    pub fn subscribe(&self) -> Subscription {
+        let (sender, receiver) = async_channel::unbounded();
+        self.subscribers().push(sender);
+        Subscription {
+            receiver: Box::pin(receiver),
+        }
+    }
+
+    /// Apply one event, notify subscribers and return the resulting changes
+    ///
+    /// Detaching a hub removes everything below it. Attaching at a path
+    /// that is already taken reports `Changed`, unless the device is
+    /// identical, in which case nothing changes.
+    pub fn apply(&self, event: TreeEvent) -> Vec<TreeChange> {
+        let changes: Vec<TreeChange> = {
+            let mut tree = self.write();
+            match event {
+                TreeEvent::Attached(device) => attach(&mut tree, device).into_iter().collect(),
+                TreeEvent::Detached { path, address } => {
+                    // A late detach must not remove the device that replaced it
+                    let replaced = address.is_some_and(|address| {
+                        tree.get_by_path(&path).is_some_and(|device| device.address != address)
+                    });
+                    if replaced {
+                        Vec::new()
+                    } else {
+                        tree.remove_subtree(&path)
+                            .into_iter()
+                            .map(|(path, device)| TreeChange::Removed { path, device })
+                            .collect()
+                    }
+                }
+            }
+        };
+        self.publish(&changes);
+        changes
+    }
+
+    /// Replace the whole tree with a fresh list of devices
+    ///
+    /// Reports the differences as changes, so subscribers see devices that
+    /// came or went while nobody was watching.
+    pub fn sync(&self, devices: Vec<UsbDevice>) -> Vec<TreeChange> {
+        let changes = {
+            let mut tree = self.write();
+            let mut fresh: HashMap<DevicePath, UsbDevice> =
+                devices.into_iter().map(|d| (d.path(), d)).collect();
+
+            let gone: Vec<DevicePath> = tree
+                .all_devices()
+                .map(|(path, _)| path)
+                .filter(|path| !fresh.contains_key(path))
+                .cloned()
+                .collect();
+            let mut changes: Vec<TreeChange> = gone
+                .into_iter()
+                .filter_map(|path| tree.remove(&path).map(|device| TreeChange::Removed { path, device }))
+                .collect();
+
+            let mut paths: Vec<DevicePath> = fresh.keys().cloned().collect();
+            paths.sort();
+            for path in paths {
+                if let Some(device) = fresh.remove(&path) {
+                    changes.extend(attach(&mut tree, device));
+                }
+            }
+            changes
+        };
+        self.publish(&changes);
+        changes
+    }
+
+    /// Follow the events of a source until it is exhausted
+    ///
+    /// Starts by syncing the tree with the source's snapshot.
+    pub async fn run(&self, source: &mut impl EventSource) -> Result<(), UsbTreeError> {
+        let devices = source.snapshot().await?;
+        self.sync(devices);
+        while let Some(event) = source.next_event().await {
+            self.apply(event);
+        }
+        Ok(())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, UsbTree<UsbDevice>> {
+        self.shared.tree.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn subscribers(&self) -> MutexGuard<'_, Vec<async_channel::Sender<TreeChange>>> {
+        self.shared.subscribers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn publish(&self, changes: &[TreeChange]) {
+        if changes.is_empty() {
+            return;
+        }
+        // Subscribers that were dropped are forgotten
+        self.subscribers().retain(|sender| {
+            changes
+                .iter()
+                .all(|change| sender.try_send(change.clone()).is_ok())
+        });
+    }
+}
+
+/// Insert a device, describing what changed at its path
+fn attach(tree: &mut UsbTree<UsbDevice>, device: UsbDevice) -> Option<TreeChange> {
+    let path = device.path();
+    match tree.get_by_path(&path) {
+        Some(old) if *old == device => None,
+        Some(_) => {
+            let old = tree.replace(&path, device.clone())?;
+            Some(TreeChange::Changed { path, old, new: device })
+        }
+        None => {
+            tree.insert_path(&path, device.clone());
+            Some(TreeChange::Added { path, device })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::device;
+
+    #[test]
+    fn test_attach_detach_and_change() {
+        let live = LiveTree::new();
+        let changes = live.subscribe();
+
+        live.apply(TreeEvent::Attached(device("1:1").with_address(2).build()));
+        live.apply(TreeEvent::Attached(device("1:1.4").with_address(3).build()));
+        let same = device("1:1.4").with_address(3).build();
+        assert!(live.apply(TreeEvent::Attached(same)).is_empty());
+        live.apply(TreeEvent::Attached(device("1:1.4").with_address(5).build()));
+
+        let received: Vec<TreeChange> = std::iter::from_fn(|| changes.try_recv()).collect();
+        assert_eq!(received.len(), 3);
+        assert!(matches!(received[2], TreeChange::Changed { ref old, .. } if old.address == 3));
+
+        // The detach of the replaced device arriving late leaves its successor
+        let late = TreeEvent::Detached {
+            path: DevicePath::new(1, vec![1, 4]),
+            address: Some(3),
+        };
+        assert!(live.apply(late).is_empty());
+        assert_eq!(live.read().get("1:1.4").map(|d| d.address), Some(5));
+
+        // Detaching the hub removes its children too
+        let hub = TreeEvent::Detached {
+            path: DevicePath::new(1, vec![1]),
+            address: Some(2),
+        };
+        let removed = live.apply(hub);
+        let paths: Vec<String> = removed.iter().map(|c| c.path().to_string()).collect();
+        assert_eq!(paths, ["1:1", "1:1.4"]);
+        assert!(live.read().is_empty());
+        let gone = TreeEvent::Detached {
+            path: DevicePath::new(1, vec![1, 4]),
+            address: None,
+        };
+        assert!(live.apply(gone).is_empty());
+    }
+
+    #[test]
+    fn test_filter_splits_changes() {
+        let change = TreeChange::Changed {
+            path: DevicePath::new(1, vec![4]),
+            old: device("1:4").with_address(3).build(),
+            new: device("1:4").with_address(5).build(),
+        };
+        let filtered = change.clone().filter(|d| d.address == 3);
+        assert!(matches!(filtered, Some(TreeChange::Removed { ref device, .. }) if device.address == 3));
+        let filtered = change.clone().filter(|d| d.address == 5);
+        assert!(matches!(filtered, Some(TreeChange::Added { ref device, .. }) if device.address == 5));
+        assert_eq!(change.clone().filter(|_| true), Some(change.clone()));
+        assert_eq!(change.filter(|_| false), None);
+    }
+
+    #[test]
+    fn test_sync_reports_differences() {
+        let live = LiveTree::new();
+        let kept = device("1:2").with_address(3).build();
+        live.sync(vec![device("1:1").with_address(2).build(), kept.clone()]);
+
+        let changes = live.sync(vec![kept, device("1:3").with_address(4).build()]);
+        let summary: Vec<String> = changes
+            .iter()
+            .map(|c| match c {
+                TreeChange::Added { path, .. } => format!("+{}", path),
+                TreeChange::Removed { path, .. } => format!("-{}", path),
+                TreeChange::Changed { path, .. } => format!("~{}", path),
+            })
+            .collect();
+        assert_eq!(summary, ["-1:1", "+1:3"]);
+    }
+
+    #[test]
+    fn test_dropped_subscribers_are_forgotten() {
+        let live = LiveTree::new();
+        drop(live.subscribe());
+        let kept = live.subscribe();
+        live.apply(TreeEvent::Attached(device("1:1").with_address(2).build()));
+        assert_eq!(live.subscribers().len(), 1);
+        assert!(kept.try_recv().is_some());
+    }
+}
@@ -0,0 +1,525 @@
+//! Manifests of the devices a machine is expected to have
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use colored::Colorize;
+use nusb::Speed;
+use serde::Deserialize;
+
+use crate::device::{deserialize_speed, speed_name, UsbDevice};
+use crate::error::{LabelError, ManifestError};
+use crate::labels::{DeviceLocation, Labels};
+use crate::path::DevicePath;
+use crate::tree::UsbTree;
+use crate::vidpid::VidPidPattern;
+
+/// The devices a test rig is expected to have
+///
+/// Each `[[device]]` entry must be matched by its own connected device;
+/// a device never satisfies two entries, so two identical entries need two
+/// identical devices. All fields are optional; the ones given must all hold.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{Labels, Manifest, UsbDevice, UsbTree};
+///
+/// let manifest: Manifest = r#"
+///     [[device]]
+///     name = "dut-relay"
+///     path = "1:2.*"
+///     vid_pid = "0403:6001"
+///     driver = "ftdi_sio"
+/// "#.parse().unwrap();
+///
+/// let mut tree = UsbTree::new();
+/// let relay = UsbDevice { bus: 1, port_path: vec![2, 4], vid: 0x0403, pid: 0x6001, ..Default::default() };
+/// tree.insert_path(&relay.path(), relay);
+///
+/// let report = manifest.check(&tree, &Labels::new()).unwrap();
+/// assert!(!report.passed());
+/// assert_eq!(report.results[0].drift[0].to_string(), "driver ftdi_sio not bound (bound: none)");
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    /// Expected devices
+    #[serde(rename = "device")]
+    pub devices: Vec<ExpectedDevice>,
+}
+
+/// One expected device in a [`Manifest`]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpectedDevice {
+    /// Name to show in reports
+    pub name: Option<String>,
+    /// Path, path pattern or `@label` the device must be at
+    pub path: Option<DeviceLocation>,
+    /// VID:PID pattern the device must match, or must not match if negated
+    pub vid_pid: Option<VidPidPattern>,
+    /// Serial number the device must have
+    pub serial: Option<String>,
+    /// Slowest acceptable speed
+    #[serde(deserialize_with = "deserialize_speed")]
+    pub min_speed: Option<Speed>,
+    /// Kernel driver that must be bound to one of the device's interfaces
+    pub driver: Option<String>,
+}
+
+impl ExpectedDevice {
+    /// Describe the entry by its name, or by what it expects
+    pub fn description(&self) -> String {
+        if let Some(ref name) = self.name {
+            return name.clone();
+        }
+        let mut parts = Vec::new();
+        if let Some(ref vid_pid) = self.vid_pid {
+            parts.push(vid_pid.to_string());
+        }
+        if let Some(ref serial) = self.serial {
+            parts.push(format!("serial {}", serial));
+        }
+        if let Some(ref path) = self.path {
+            parts.push(format!("at {}", path));
+        }
+        if parts.is_empty() {
+            "any device".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+
+    /// Check whether a device has the expected identity and location
+    fn identifies(&self, path: &DevicePath, device: &UsbDevice, labels: &Labels) -> bool {
+        self.path.as_ref().is_none_or(|p| p.matches(path, device, labels))
+            && self.vid_pid.is_none_or(|p| p.matches(device) != p.negated)
+            && self.serial.as_ref().is_none_or(|s| device.serial.as_ref() == Some(s))
+    }
+
+    /// Requirements the device does not meet
+    fn drift(&self, device: &UsbDevice) -> Vec<Drift> {
+        let mut drift = Vec::new();
+        if let Some(min) = self.min_speed
+            && device.speed.is_none_or(|speed| speed < min)
+        {
+            drift.push(Drift::TooSlow {
+                speed: device.speed,
+                min,
+            });
+        }
+        if let Some(ref driver) = self.driver
+            && !device.drivers.contains(driver)
+        {
+            drift.push(Drift::DriverNotBound {
+                driver: driver.clone(),
+                bound: device.drivers.clone(),
+            });
+        }
+        drift
+    }
+}
+
+/// A way in which the machine differs from its manifest
+#[derive(Debug, Clone, PartialEq)]
+pub enum Drift {
+    /// No device matches the entry
+    Missing,
+    /// The expected path holds a different device
+    WrongDevice {
+        /// Where the device was expected
+        path: DevicePath,
+        /// The device found there instead
+        found: UsbDevice,
+    },
+    /// The device runs slower than required
+    TooSlow {
+        /// Speed of the device, if known
+        speed: Option<Speed>,
+        /// Slowest acceptable speed
+        min: Speed,
+    },
+    /// The required driver is not bound to any interface
+    DriverNotBound {
+        /// The required driver
+        driver: String,
+        /// Drivers that are bound
+        bound: Vec<String>,
+    },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Missing => write!(f, "not connected"),
+            Drift::WrongDevice { path, found } => {
+                write!(f, "found {} at {} instead", found.vid_pid(), path)?;
+                if let Some(ref serial) = found.serial {
+                    write!(f, " (serial {})", serial)?;
+                }
+                Ok(())
+            }
+            Drift::TooSlow { speed, min } => write!(
+                f,
+                "speed {}, expected at least {}",
+                speed.map(speed_name).unwrap_or("unknown"),
+                speed_name(*min)
+            ),
+            Drift::DriverNotBound { driver, bound } => {
+                let bound = if bound.is_empty() {
+                    "none".to_string()
+                } else {
+                    bound.join(", ")
+                };
+                write!(f, "driver {} not bound (bound: {})", driver, bound)
+            }
+        }
+    }
+}
+
+/// The outcome of checking one manifest entry
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    /// Description of the entry
+    pub description: String,
+    /// The device that satisfied the entry or came closest
+    pub path: Option<DevicePath>,
+    /// Everything that is wrong; empty if the entry passed
+    pub drift: Vec<Drift>,
+}
+
+impl CheckResult {
+    /// Check whether the entry passed
+    pub fn passed(&self) -> bool {
+        self.drift.is_empty()
+    }
+}
+
+/// The outcome of checking a whole manifest
+///
+/// Displays as a pass/fail line per entry, colored when colors are on.
+#[derive(Debug, Clone)]
+pub struct ManifestReport {
+    /// One result per manifest entry, in manifest order
+    pub results: Vec<CheckResult>,
+}
+
+impl ManifestReport {
+    /// Check whether every entry passed
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(CheckResult::passed)
+    }
+
+    /// Number of entries that failed
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed()).count()
+    }
+}
+
+impl fmt::Display for ManifestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let status = if result.passed() {
+                "PASS".green().bold()
+            } else {
+                "FAIL".red().bold()
+            };
+            write!(f, "{} {}", status, result.description)?;
+            if let Some(ref path) = result.path {
+                write!(f, " ({})", path)?;
+            }
+            writeln!(f)?;
+            for drift in &result.drift {
+                writeln!(f, "     {}", drift.to_string().yellow())?;
+            }
+        }
+        let failures = self.failures();
+        let summary = format!("{} of {} checks passed", self.results.len() - failures, self.results.len());
+        if failures == 0 {
+            writeln!(f, "{}", summary.green())
+        } else {
+            writeln!(f, "{}", summary.red())
+        }
+    }
+}
+
+impl Manifest {
+    /// Load a manifest from a TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(|source| ManifestError::Io {
+                path: path.to_path_buf(),
+                source,
+            })?
+            .parse()
+    }
+
+    /// Check a device tree against the manifest
+    ///
+    /// Devices are assigned to entries so that as many entries as possible
+    /// pass; entries that cannot pass are reported against a device no
+    /// other entry uses, if one comes close. Fails if an entry refers to
+    /// an unknown label.
+    pub fn check(&self, tree: &UsbTree<UsbDevice>, labels: &Labels) -> Result<ManifestReport, LabelError> {
+        for expected in &self.devices {
+            if let Some(ref path) = expected.path {
+                path.validate(labels)?;
+            }
+        }
+        let devices: Vec<(&DevicePath, &UsbDevice)> = tree.all_devices().collect();
+        let candidates = |expected: &ExpectedDevice, claimed: &[bool], passing: bool| -> Vec<usize> {
+            (0..devices.len())
+                .filter(|&i| !claimed[i])
+                .filter(|&i| {
+                    let (path, device) = devices[i];
+                    expected.identifies(path, device, labels) && (!passing || expected.drift(device).is_empty())
+                })
+                .collect()
+        };
+
+        // First give passing devices to as many entries as possible, then
+        // pair the rest with the closest unclaimed devices
+        let mut assigned = vec![None; self.devices.len()];
+        let mut claimed = vec![false; devices.len()];
+        for passing in [true, false] {
+            let edges: Vec<Vec<usize>> = self
+                .devices
+                .iter()
+                .zip(&assigned)
+                .map(|(expected, assigned)| match assigned {
+                    Some(_) => Vec::new(),
+                    None => candidates(expected, &claimed, passing),
+                })
+                .collect();
+            for (entry, device) in assign(&edges, devices.len()).into_iter().enumerate() {
+                if let Some(device) = device {
+                    assigned[entry] = Some(device);
+                    claimed[device] = true;
+                }
+            }
+        }
+
+        let results = self
+            .devices
+            .iter()
+            .zip(assigned)
+            .map(|(expected, device)| check_entry(expected, device.map(|i| devices[i]), tree, labels))
+            .collect();
+        Ok(ManifestReport { results })
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = ManifestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+/// Match entries to devices, each device to at most one entry
+///
+/// `edges[entry]` lists the devices an entry accepts. Finds a maximum
+/// matching with augmenting paths, preferring earlier entries and devices.
+fn assign(edges: &[Vec<usize>], devices: usize) -> Vec<Option<usize>> {
+    fn augment(entry: usize, edges: &[Vec<usize>], owner: &mut [Option<usize>], seen: &mut [bool]) -> bool {
+        for &device in &edges[entry] {
+            if seen[device] {
+                continue;
+            }
+            seen[device] = true;
+            if owner[device].is_none_or(|other| augment(other, edges, owner, seen)) {
+                owner[device] = Some(entry);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owner = vec![None; devices];
+    for entry in 0..edges.len() {
+        augment(entry, edges, &mut owner, &mut vec![false; devices]);
+    }
+    let mut assigned = vec![None; edges.len()];
+    for (device, entry) in owner.into_iter().enumerate() {
+        if let Some(entry) = entry {
+            assigned[entry] = Some(device);
+        }
+    }
+    assigned
+}
+
+/// List what is wrong with the device assigned to an entry
+fn check_entry(
+    expected: &ExpectedDevice,
+    assigned: Option<(&DevicePath, &UsbDevice)>,
+    tree: &UsbTree<UsbDevice>,
+    labels: &Labels,
+) -> CheckResult {
+    let result = |path: Option<&DevicePath>, drift| CheckResult {
+        description: expected.description(),
+        path: path.cloned(),
+        drift,
+    };
+    if let Some((path, device)) = assigned {
+        return result(Some(path), expected.drift(device));
+    }
+
+    // Nothing left matches; say what occupies the expected path, if anything
+    let occupant = expected.path.as_ref().and_then(|location| {
+        tree.all_devices()
+            .find(|(path, device)| location.matches_path(path, labels) && !device.is_hub())
+    });
+    match occupant {
+        Some((path, device)) => result(Some(path), vec![Drift::WrongDevice {
+            path: path.clone(),
+            found: device.clone(),
+        }]),
+        None => result(None, vec![Drift::Missing]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::LabelTarget;
+    use crate::test_support::{self, device};
+
+    fn tree() -> UsbTree<UsbDevice> {
+        test_support::tree([
+            device("1:2").with_class(9).build(),
+            device("1:2.1")
+                .with_id(0x2e8a, 0x000a)
+                .with_serial("E660")
+                .with_speed(Speed::Full)
+                .with_driver("cdc_acm")
+                .build(),
+            device("1:3").with_id(0x0403, 0x6001).build(),
+        ])
+    }
+
+    fn check(manifest: &str) -> Vec<Vec<String>> {
+        let mut labels = Labels::new();
+        labels.insert("pico", LabelTarget::Serial("E660".to_string()));
+        labels.insert("slot-4", LabelTarget::Path("1:4".parse().unwrap()));
+        let manifest: Manifest = manifest.parse().unwrap();
+        manifest
+            .check(&tree(), &labels)
+            .unwrap()
+            .results
+            .iter()
+            .map(|r| r.drift.iter().map(|d| d.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_passing_entries() {
+        let drift = check(
+            r#"
+            [[device]]
+            vid_pid = "2e8a:*"
+            serial = "E660"
+            min_speed = "full"
+            driver = "cdc_acm"
+
+            [[device]]
+            path = "1:3"
+        "#,
+        );
+        assert_eq!(drift, [Vec::<String>::new(), Vec::new()]);
+    }
+
+    #[test]
+    fn test_drift() {
+        let drift = check(
+            r#"
+            [[device]]
+            path = "1:2.*"
+            min_speed = "high"
+            driver = "ftdi_sio"
+
+            [[device]]
+            path = "1:3"
+            vid_pid = "0403:6015"
+
+            [[device]]
+            serial = "nope"
+
+            [[device]]
+            path = "1:2.1"
+            vid_pid = "!2e8a:*"
+        "#,
+        );
+        assert_eq!(drift, [
+            vec![
+                "speed full, expected at least high".to_string(),
+                "driver ftdi_sio not bound (bound: cdc_acm)".to_string(),
+            ],
+            vec!["found 0403:6001 at 1:3 instead".to_string()],
+            vec!["not connected".to_string()],
+            vec!["found 2e8a:000a at 1:2.1 instead (serial E660)".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn test_devices_are_not_shared() {
+        let drift = check(
+            r#"
+            [[device]]
+            vid_pid = "0403:6001"
+
+            [[device]]
+            vid_pid = "0403:6001"
+        "#,
+        );
+        assert_eq!(drift, [vec![], vec!["not connected".to_string()]]);
+
+        // Everything but the hub: the Pico at 1:2.1 is listed first, but the
+        // generic entry must take the FTDI and leave the Pico to its serial
+        let manifest: Manifest = r#"
+            [[device]]
+            vid_pid = "!0000:0000"
+
+            [[device]]
+            serial = "E660"
+        "#
+        .parse()
+        .unwrap();
+        let report = manifest.check(&tree(), &Labels::new()).unwrap();
+        let paths: Vec<String> = report
+            .results
+            .iter()
+            .map(|r| r.path.as_ref().map_or("-".to_string(), |p| p.to_string()))
+            .collect();
+        assert_eq!(paths, ["1:3", "1:2.1"]);
+    }
+
+    #[test]
+    fn test_label_locations() {
+        let drift = check(
+            r#"
+            [[device]]
+            path = "@pico"
+            driver = "cdc_acm"
+
+            [[device]]
+            path = "@slot-4"
+        "#,
+        );
+        assert_eq!(drift, [vec![], vec!["not connected".to_string()]]);
+
+        let manifest: Manifest = "[[device]]\npath = \"@nope\"".parse().unwrap();
+        assert!(manifest.check(&tree(), &Labels::new()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        let err = "[[device]]\nmin_speed = \"warp\"".parse::<Manifest>().unwrap_err();
+        assert!(err.to_string().contains("unknown speed"));
+        assert!("[[device]]\nvid_pid = \"zz\"".parse::<Manifest>().is_err());
+    }
+}
@@ -0,0 +1,472 @@
+//! Conversions between `DevicePath` and kernel, udev and lsusb notations
+
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use crate::device::UsbDevice;
+use crate::error::DevicePathError;
+use crate::path::DevicePath;
+use crate::tree::UsbTree;
+
+/// Sysfs directory listing USB devices and interfaces
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+/// Parse a kernel port chain like "2.3" (or "0" for a root hub)
+fn parse_sysfs_ports(s: &str) -> Result<Vec<u8>, DevicePathError> {
+    if s == "0" {
+        return Ok(vec![]);
+    }
+    s.split('.')
+        .map(|p| {
+            p.parse::<u8>()
+                .map_err(|_| DevicePathError::InvalidPort(p.to_string()))
+        })
+        .collect()
+}
+
+/// Parse a "config.interface" suffix like "1.0"
+fn parse_config_interface(s: &str) -> Result<(u8, u8), DevicePathError> {
+    let invalid = || DevicePathError::InvalidNotation(s.to_string());
+    let (config, interface) = s.split_once('.').ok_or_else(invalid)?;
+    Ok((
+        config.parse().map_err(|_| invalid())?,
+        interface.parse().map_err(|_| invalid())?,
+    ))
+}
+
+fn join_ports(ports: &[u8]) -> String {
+    ports
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+impl DevicePath {
+    /// Parse a sysfs device name such as `1-2.3`, or `usb1` for a root hub
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use usbinfo::DevicePath;
+    ///
+    /// let path = DevicePath::from_sysfs_name("1-2.3").unwrap();
+    /// assert_eq!(path.to_string(), "1:2.3");
+    /// assert_eq!(path.to_sysfs_name(), "1-2.3");
+    /// assert_eq!(DevicePath::from_sysfs_name("usb2").unwrap(), DevicePath::bus_only(2));
+    /// ```
+    pub fn from_sysfs_name(name: &str) -> Result<DevicePath, DevicePathError> {
+        if let Some(bus) = name.strip_prefix("usb") {
+            let bus = bus
+                .parse()
+                .map_err(|_| DevicePathError::InvalidBus(bus.to_string()))?;
+            return Ok(DevicePath::bus_only(bus));
+        }
+
+        let (bus_str, port_str) = name
+            .split_once('-')
+            .ok_or_else(|| DevicePathError::InvalidNotation(name.to_string()))?;
+        if port_str.contains(':') {
+            return Err(DevicePathError::InvalidNotation(name.to_string()));
+        }
+        let bus = bus_str
+            .parse()
+            .map_err(|_| DevicePathError::InvalidBus(bus_str.to_string()))?;
+        Ok(DevicePath::new(bus, parse_sysfs_ports(port_str)?))
+    }
+
+    /// Format as a sysfs device name (`1-2.3`, or `usb1` for a root hub)
+    pub fn to_sysfs_name(&self) -> String {
+        if self.is_bus_only() {
+            format!("usb{}", self.bus())
+        } else {
+            format!("{}-{}", self.bus(), join_ports(self.ports()))
+        }
+    }
+
+    /// Resolve a path given in any supported notation
+    ///
+    /// Accepts `bus:port.port`, sysfs device and interface names, udev
+    /// `ID_PATH` strings and lsusb's `Bus 001 Device 004`. The last two
+    /// are looked up in `tree`.
+    pub fn resolve(spec: &str, tree: &UsbTree<UsbDevice>) -> Result<DevicePath, DevicePathError> {
+        let spec = spec.trim();
+        if let Ok(path) = spec.parse::<DevicePath>()
+            && !spec.contains('-')
+        {
+            return Ok(path);
+        }
+        if let Ok(path) = DevicePath::from_sysfs_name(spec) {
+            return Ok(path);
+        }
+        if let Ok(interface) = spec.parse::<InterfacePath>() {
+            return Ok(interface.device);
+        }
+        if let Ok(address) = spec.parse::<LsusbAddress>() {
+            return address
+                .resolve(tree)
+                .ok_or_else(|| DevicePathError::Unresolved(spec.to_string()));
+        }
+        if let Ok(id_path) = spec.parse::<UdevIdPath>() {
+            return id_path
+                .resolve(tree)
+                .ok_or_else(|| DevicePathError::Unresolved(spec.to_string()));
+        }
+        Err(DevicePathError::InvalidNotation(spec.to_string()))
+    }
+}
+
+/// A USB interface: a device path plus configuration and interface number
+///
+/// Parses and formats the sysfs interface notation `1-2.3:1.0`.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::InterfacePath;
+///
+/// let iface: InterfacePath = "1-2.3:1.0".parse().unwrap();
+/// assert_eq!(iface.device.to_string(), "1:2.3");
+/// assert_eq!((iface.config, iface.interface), (1, 0));
+/// assert_eq!(iface.to_string(), "1-2.3:1.0");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InterfacePath {
+    /// Path of the device the interface belongs to
+    pub device: DevicePath,
+    /// Configuration value
+    pub config: u8,
+    /// Interface number
+    pub interface: u8,
+}
+
+impl FromStr for InterfacePath {
+    type Err = DevicePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (device_str, iface_str) = s
+            .split_once(':')
+            .ok_or_else(|| DevicePathError::InvalidNotation(s.to_string()))?;
+        let (bus_str, port_str) = device_str
+            .split_once('-')
+            .ok_or_else(|| DevicePathError::InvalidNotation(s.to_string()))?;
+        let bus = bus_str
+            .parse()
+            .map_err(|_| DevicePathError::InvalidBus(bus_str.to_string()))?;
+        let (config, interface) = parse_config_interface(iface_str)?;
+
+        Ok(InterfacePath {
+            device: DevicePath::new(bus, parse_sysfs_ports(port_str)?),
+            config,
+            interface,
+        })
+    }
+}
+
+impl fmt::Display for InterfacePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports = if self.device.is_bus_only() {
+            "0".to_string()
+        } else {
+            join_ports(self.device.ports())
+        };
+        write!(
+            f,
+            "{}-{}:{}.{}",
+            self.device.bus(),
+            ports,
+            self.config,
+            self.interface
+        )
+    }
+}
+
+/// A udev `ID_PATH` such as `pci-0000:00:14.0-usb-0:2.3:1.0`
+///
+/// `ID_PATH` names the host controller instead of the bus number, so
+/// converting to a [`DevicePath`] needs the controller-to-bus mapping
+/// from sysfs (see [`UdevIdPath::resolve`]) or an explicit bus.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::UdevIdPath;
+///
+/// let id: UdevIdPath = "pci-0000:00:14.0-usb-0:2.3:1.0".parse().unwrap();
+/// assert_eq!(id.controller, "pci-0000:00:14.0");
+/// assert_eq!(id.ports, vec![2, 3]);
+/// assert_eq!(id.interface, Some((1, 0)));
+/// assert_eq!(id.to_device_path(3).to_string(), "3:2.3");
+/// assert_eq!(id.to_string(), "pci-0000:00:14.0-usb-0:2.3:1.0");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UdevIdPath {
+    /// Host controller part, e.g. `pci-0000:00:14.0`
+    pub controller: String,
+    /// Port chain below the root hub
+    pub ports: Vec<u8>,
+    /// Configuration and interface number, for interface paths
+    pub interface: Option<(u8, u8)>,
+    /// USB revision written as `usbv2` or `usbv3`, as in
+    /// `ID_PATH_WITH_USB_REVISION`; `None` for a plain `usb`
+    pub revision: Option<u8>,
+}
+
+impl UdevIdPath {
+    /// Build the `ID_PATH` of a device below a controller
+    pub fn new(controller: impl Into<String>, path: &DevicePath) -> Self {
+        Self {
+            controller: controller.into(),
+            ports: path.ports().to_vec(),
+            interface: None,
+            revision: None,
+        }
+    }
+
+    /// Build the `ID_PATH` of a device, looking up its controller in sysfs
+    pub fn for_path(path: &DevicePath) -> Option<Self> {
+        controller_of_bus(path.bus()).map(|controller| Self::new(controller, path))
+    }
+
+    /// Convert to a device path on the given bus
+    pub fn to_device_path(&self, bus: u8) -> DevicePath {
+        DevicePath::new(bus, self.ports.clone())
+    }
+
+    /// Find the device this `ID_PATH` refers to
+    ///
+    /// USB 2 and USB 3 root hubs of one xHCI controller share an
+    /// `ID_PATH`, so the bus is chosen by which one has a device at the
+    /// port chain.
+    pub fn resolve(&self, tree: &UsbTree<UsbDevice>) -> Option<DevicePath> {
+        buses_of_controller(&self.controller)
+            .into_iter()
+            .map(|bus| self.to_device_path(bus))
+            .find(|path| tree.get_by_path(path).is_some())
+    }
+}
+
+impl FromStr for UdevIdPath {
+    type Err = DevicePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DevicePathError::InvalidNotation(s.to_string());
+
+        // "usbv2"/"usbv3" appear in ID_PATH_WITH_USB_REVISION
+        let separators = [("-usb-", None), ("-usbv2-", Some(2)), ("-usbv3-", Some(3))];
+        let (controller, rest, revision) = separators
+            .iter()
+            .find_map(|&(sep, revision)| {
+                let (controller, rest) = s.rsplit_once(sep)?;
+                Some((controller, rest, revision))
+            })
+            .ok_or_else(invalid)?;
+        let rest = rest.strip_prefix("0:").ok_or_else(invalid)?;
+        let (ports, interface) = match rest.split_once(':') {
+            Some((ports, iface)) => (ports, Some(parse_config_interface(iface)?)),
+            None => (rest, None),
+        };
+
+        Ok(UdevIdPath {
+            controller: controller.to_string(),
+            ports: parse_sysfs_ports(ports)?,
+            interface,
+            revision,
+        })
+    }
+}
+
+impl fmt::Display for UdevIdPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports = if self.ports.is_empty() {
+            "0".to_string()
+        } else {
+            join_ports(&self.ports)
+        };
+        match self.revision {
+            Some(revision) => write!(f, "{}-usbv{}-0:{}", self.controller, revision, ports)?,
+            None => write!(f, "{}-usb-0:{}", self.controller, ports)?,
+        }
+        if let Some((config, interface)) = self.interface {
+            write!(f, ":{}.{}", config, interface)?;
+        }
+        Ok(())
+    }
+}
+
+/// Strip the subsystem prefix from an `ID_PATH` controller (`pci-0000:00:14.0` -> `0000:00:14.0`)
+fn controller_device_name(controller: &str) -> &str {
+    controller
+        .split_once('-')
+        .map_or(controller, |(_, name)| name)
+}
+
+/// Name of the device directory a root hub hangs off in sysfs
+fn root_hub_parent(bus: u8) -> Option<String> {
+    let root = fs::canonicalize(format!("{}/usb{}", SYSFS_USB_DEVICES, bus)).ok()?;
+    Some(root.parent()?.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Find the `ID_PATH` controller part for a bus, e.g. `pci-0000:00:14.0`
+fn controller_of_bus(bus: u8) -> Option<String> {
+    let parent = root_hub_parent(bus)?;
+    let subsystem = fs::canonicalize(format!("{}/usb{}/../subsystem", SYSFS_USB_DEVICES, bus))
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "pci".to_string());
+    Some(format!("{}-{}", subsystem, parent))
+}
+
+/// Find all buses whose root hub belongs to a controller
+fn buses_of_controller(controller: &str) -> Vec<u8> {
+    let name = controller_device_name(controller);
+    let Ok(entries) = fs::read_dir(SYSFS_USB_DEVICES) else {
+        return vec![];
+    };
+    let mut buses: Vec<u8> = entries
+        .filter_map(|entry| {
+            entry
+                .ok()?
+                .file_name()
+                .to_str()?
+                .strip_prefix("usb")?
+                .parse()
+                .ok()
+        })
+        .filter(|&bus| root_hub_parent(bus).as_deref() == Some(name))
+        .collect();
+    buses.sort();
+    buses
+}
+
+/// lsusb's bus and device number notation, `Bus 001 Device 004`
+///
+/// Device numbers are assigned at enumeration time, so converting to a
+/// [`DevicePath`] requires looking the device up in a tree.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::LsusbAddress;
+///
+/// let addr: LsusbAddress = "Bus 001 Device 004: ID 0483:5740 STM32".parse().unwrap();
+/// assert_eq!((addr.bus, addr.address), (1, 4));
+/// assert_eq!(addr.to_string(), "Bus 001 Device 004");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LsusbAddress {
+    /// Bus number
+    pub bus: u8,
+    /// Device address on the bus
+    pub address: u8,
+}
+
+impl LsusbAddress {
+    /// Get the lsusb address of a device
+    pub fn of(device: &UsbDevice) -> Self {
+        Self {
+            bus: device.bus,
+            address: device.address,
+        }
+    }
+
+    /// Find the path of the device with this address
+    pub fn resolve(&self, tree: &UsbTree<UsbDevice>) -> Option<DevicePath> {
+        tree.find_by_bus_address(self.bus, self.address)
+            .map(|(path, _)| path)
+    }
+}
+
+impl FromStr for LsusbAddress {
+    type Err = DevicePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DevicePathError::InvalidNotation(s.to_string());
+        let mut words = s.split_whitespace();
+        let mut field = |label: &str| -> Result<u8, DevicePathError> {
+            if !words.next().is_some_and(|w| w.eq_ignore_ascii_case(label)) {
+                return Err(invalid());
+            }
+            words
+                .next()
+                .map(|n| n.trim_end_matches(':'))
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let bus = field("bus")?;
+        let address = field("device")?;
+        Ok(LsusbAddress { bus, address })
+    }
+}
+
+impl fmt::Display for LsusbAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bus {:03} Device {:03}", self.bus, self.address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, device};
+
+    #[test]
+    fn test_sysfs_names() {
+        assert_eq!(
+            DevicePath::from_sysfs_name("3-1.4.2").unwrap(),
+            DevicePath::new(3, vec![1, 4, 2])
+        );
+        assert_eq!(DevicePath::bus_only(1).to_sysfs_name(), "usb1");
+        assert!(DevicePath::from_sysfs_name("1-2.3:1.0").is_err());
+        assert!(DevicePath::from_sysfs_name("1:2").is_err());
+    }
+
+    #[test]
+    fn test_root_hub_interface() {
+        let iface: InterfacePath = "2-0:1.0".parse().unwrap();
+        assert_eq!(iface.device, DevicePath::bus_only(2));
+        assert_eq!(iface.to_string(), "2-0:1.0");
+    }
+
+    #[test]
+    fn test_udev_id_path_variants() {
+        let id: UdevIdPath = "platform-xhci-hcd.0.auto-usbv3-0:1".parse().unwrap();
+        assert_eq!(id.controller, "platform-xhci-hcd.0.auto");
+        assert_eq!(id.ports, vec![1]);
+        assert_eq!(id.interface, None);
+        assert_eq!(id.revision, Some(3));
+
+        for text in [
+            "pci-0000:00:14.0-usb-0:2.3:1.0",
+            "pci-0000:00:14.0-usbv2-0:2.3",
+            "platform-xhci-hcd.0.auto-usbv3-0:1:1.0",
+        ] {
+            assert_eq!(text.parse::<UdevIdPath>().unwrap().to_string(), text);
+        }
+
+        let id = UdevIdPath::new("pci-0000:00:14.0", &DevicePath::new(1, vec![2, 3]));
+        assert_eq!(id.to_string(), "pci-0000:00:14.0-usb-0:2.3");
+        assert!("pci-0000:00:14.0".parse::<UdevIdPath>().is_err());
+    }
+
+    #[test]
+    fn test_resolve_lsusb() {
+        let tree = test_support::tree([device("1:2.3").with_id(0x0483, 0x5740).with_address(4).build()]);
+
+        let expected = DevicePath::new(1, vec![2, 3]);
+        assert_eq!(
+            DevicePath::resolve("Bus 001 Device 004", &tree).unwrap(),
+            expected
+        );
+        assert_eq!(DevicePath::resolve("1-2.3", &tree).unwrap(), expected);
+        assert_eq!(DevicePath::resolve("1-2.3:1.0", &tree).unwrap(), expected);
+        assert_eq!(DevicePath::resolve("1:2.3", &tree).unwrap(), expected);
+        assert_eq!(
+            DevicePath::resolve("Bus 001 Device 009", &tree),
+            Err(DevicePathError::Unresolved(
+                "Bus 001 Device 009".to_string()
+            ))
+        );
+    }
+}
@@ -3,10 +3,15 @@
 use std::fmt;
 use std::str::FromStr;
 
+use serde::{Deserialize, Serialize};
+
 use crate::error::DevicePathError;
 
 /// A parsed USB device path representing "bus:port.port.port" format
 ///
+/// Paths order numerically by bus, then port by port, so a hub sorts
+/// directly before its children and bus 2 before bus 10.
+///
 /// # Examples
 ///
 /// ```
@@ -26,7 +31,8 @@ use crate::error::DevicePathError;
 /// let path = DevicePath::new(1, vec![2, 3]);
 /// assert_eq!(path.to_string(), "1:2.3");
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct DevicePath {
     /// Bus number
     bus: u8,
@@ -90,6 +96,22 @@ impl DevicePath {
         other.is_ancestor_of(self)
     }
 
+    /// Get the longest path that is equal to or an ancestor of both paths
+    ///
+    /// Returns `None` for paths on different buses.
+    pub fn common_prefix(&self, other: &DevicePath) -> Option<DevicePath> {
+        if self.bus != other.bus {
+            return None;
+        }
+        let len = self
+            .ports
+            .iter()
+            .zip(&other.ports)
+            .take_while(|(a, b)| a == b)
+            .count();
+        Some(DevicePath::new(self.bus, self.ports[..len].to_vec()))
+    }
+
     /// Create a child path by appending a port
     pub fn child(&self, port: u8) -> DevicePath {
         let mut ports = self.ports.clone();
@@ -97,7 +119,7 @@ impl DevicePath {
         DevicePath { bus: self.bus, ports }
     }
 
-    /// Get the bus as a string (for HashMap keys)
+    /// Get the bus as a string
     pub fn bus_str(&self) -> String {
         self.bus.to_string()
     }
@@ -108,6 +130,20 @@ impl DevicePath {
     }
 }
 
+impl TryFrom<String> for DevicePath {
+    type Error = DevicePathError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<DevicePath> for String {
+    fn from(path: DevicePath) -> Self {
+        path.to_string()
+    }
+}
+
 impl FromStr for DevicePath {
     type Err = DevicePathError;
 
@@ -157,6 +193,198 @@ impl fmt::Display for DevicePath {
     }
 }
 
+/// One component of a [`DevicePathPattern`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// A specific number
+    Exact(u8),
+    /// Any single number (`*`)
+    Any,
+    /// Zero or more ports (`**`), only valid in the port chain
+    AnyDepth,
+    /// One of a set of numbers or inclusive ranges (`{1,3,5-7}`)
+    Set(Vec<(u8, u8)>),
+}
+
+impl Segment {
+    fn parse(s: &str, allow_any_depth: bool) -> Option<Segment> {
+        match s {
+            "*" => Some(Segment::Any),
+            "**" if allow_any_depth => Some(Segment::AnyDepth),
+            _ => {
+                if let Some(inner) = s.strip_prefix('{').and_then(|r| r.strip_suffix('}')) {
+                    let ranges = inner
+                        .split(',')
+                        .map(|item| match item.split_once('-') {
+                            Some((lo, hi)) => {
+                                let (lo, hi) = (lo.parse().ok()?, hi.parse().ok()?);
+                                (lo <= hi).then_some((lo, hi))
+                            }
+                            None => item.parse().ok().map(|n| (n, n)),
+                        })
+                        .collect::<Option<Vec<_>>>()?;
+                    Some(Segment::Set(ranges))
+                } else {
+                    s.parse().ok().map(Segment::Exact)
+                }
+            }
+        }
+    }
+
+    fn matches(&self, n: u8) -> bool {
+        match self {
+            Segment::Exact(value) => *value == n,
+            Segment::Any | Segment::AnyDepth => true,
+            Segment::Set(ranges) => ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&n)),
+        }
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Segment::Exact(n) => write!(f, "{}", n),
+            Segment::Any => write!(f, "*"),
+            Segment::AnyDepth => write!(f, "**"),
+            Segment::Set(ranges) => {
+                let items: Vec<String> = ranges
+                    .iter()
+                    .map(|(lo, hi)| {
+                        if lo == hi {
+                            lo.to_string()
+                        } else {
+                            format!("{}-{}", lo, hi)
+                        }
+                    })
+                    .collect();
+                write!(f, "{{{}}}", items.join(","))
+            }
+        }
+    }
+}
+
+/// A wildcard pattern over device paths
+///
+/// Each component is a number, `*` (any single number), `{1,3}` or
+/// `{1-4}` (a set), and in the port chain also `**` (zero or more ports).
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{DevicePath, DevicePathPattern};
+///
+/// let pattern: DevicePathPattern = "*:2.{1,3}.4".parse().unwrap();
+/// assert!(pattern.matches(&"1:2.3.4".parse().unwrap()));
+/// assert!(pattern.matches(&"5:2.1.4".parse().unwrap()));
+/// assert!(!pattern.matches(&"1:2.2.4".parse().unwrap()));
+///
+/// // Everything at or below hub 1:2
+/// let below: DevicePathPattern = "1:2.**".parse().unwrap();
+/// assert!(below.matches(&"1:2".parse().unwrap()));
+/// assert!(below.matches(&"1:2.3.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DevicePathPattern {
+    bus: Segment,
+    ports: Vec<Segment>,
+}
+
+impl DevicePathPattern {
+    /// Check whether a path matches this pattern
+    pub fn matches(&self, path: &DevicePath) -> bool {
+        self.bus.matches(path.bus) && Self::match_ports(&self.ports, &path.ports)
+    }
+
+    fn match_ports(segments: &[Segment], ports: &[u8]) -> bool {
+        match segments.split_first() {
+            None => ports.is_empty(),
+            Some((Segment::AnyDepth, rest)) => {
+                (0..=ports.len()).any(|skip| Self::match_ports(rest, &ports[skip..]))
+            }
+            Some((segment, rest)) => match ports.split_first() {
+                Some((port, remaining)) => {
+                    segment.matches(*port) && Self::match_ports(rest, remaining)
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for DevicePathPattern {
+    type Error = DevicePathError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for DevicePathPattern {
+    type Err = DevicePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bus_str, port_str) = s.split_once(':').ok_or(DevicePathError::InvalidFormat)?;
+
+        if bus_str.is_empty() {
+            return Err(DevicePathError::MissingBus);
+        }
+
+        let bus = Segment::parse(bus_str, false)
+            .ok_or_else(|| DevicePathError::InvalidBus(bus_str.to_string()))?;
+
+        let ports = if port_str.is_empty() {
+            vec![]
+        } else {
+            split_segments(port_str)
+                .into_iter()
+                .map(|p| {
+                    Segment::parse(p, true)
+                        .ok_or_else(|| DevicePathError::InvalidPort(p.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        Ok(DevicePathPattern { bus, ports })
+    }
+}
+
+/// Split a port chain pattern on dots that are not inside braces
+fn split_segments(s: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            '.' if depth == 0 => {
+                segments.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&s[start..]);
+    segments
+}
+
+impl fmt::Display for DevicePathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports: Vec<String> = self.ports.iter().map(|p| p.to_string()).collect();
+        write!(f, "{}:{}", self.bus, ports.join("."))
+    }
+}
+
+impl From<&DevicePath> for DevicePathPattern {
+    fn from(path: &DevicePath) -> Self {
+        DevicePathPattern {
+            bus: Segment::Exact(path.bus),
+            ports: path.ports.iter().map(|&p| Segment::Exact(p)).collect(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -203,4 +431,44 @@ mod tests {
         assert!(parent.is_ancestor_of(&child));
         assert!(child.is_descendant_of(&parent));
     }
+
+    #[test]
+    fn test_pattern_wildcards() {
+        let pattern: DevicePathPattern = "1:2.*".parse().unwrap();
+        assert!(pattern.matches(&DevicePath::new(1, vec![2, 7])));
+        assert!(!pattern.matches(&DevicePath::new(1, vec![2])));
+        assert!(!pattern.matches(&DevicePath::new(1, vec![2, 7, 1])));
+
+        let pattern: DevicePathPattern = "*:4.1".parse().unwrap();
+        assert!(pattern.matches(&DevicePath::new(3, vec![4, 1])));
+    }
+
+    #[test]
+    fn test_pattern_any_depth() {
+        let pattern: DevicePathPattern = "*:**.4".parse().unwrap();
+        assert!(pattern.matches(&DevicePath::new(1, vec![4])));
+        assert!(pattern.matches(&DevicePath::new(2, vec![1, 3, 4])));
+        assert!(!pattern.matches(&DevicePath::new(2, vec![4, 3])));
+    }
+
+    #[test]
+    fn test_pattern_sets() {
+        let pattern: DevicePathPattern = "{1,3}:2.{1-2,4}".parse().unwrap();
+        assert!(pattern.matches(&DevicePath::new(3, vec![2, 4])));
+        assert!(!pattern.matches(&DevicePath::new(2, vec![2, 4])));
+        assert!(!pattern.matches(&DevicePath::new(1, vec![2, 3])));
+        assert_eq!(pattern.to_string(), "{1,3}:2.{1-2,4}");
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        assert_eq!(
+            "1:2.{3".parse::<DevicePathPattern>(),
+            Err(DevicePathError::InvalidPort("{3".to_string()))
+        );
+        assert_eq!(
+            "**:2".parse::<DevicePathPattern>(),
+            Err(DevicePathError::InvalidBus("**".to_string()))
+        );
+    }
 }
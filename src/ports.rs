@@ -0,0 +1,180 @@
+//! Physical port names for a machine's chassis
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::config::config_dir;
+use crate::error::PortMapError;
+use crate::path::DevicePath;
+
+/// On-disk port map: shared entries plus per-host sections
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PortMapFile {
+    ports: HashMap<String, String>,
+    host: HashMap<String, HashMap<String, String>>,
+}
+
+/// Names for the physical ports of a machine, keyed by path prefix
+///
+/// A device is "plugged into" the named port with the longest path that
+/// is equal to or an ancestor of the device's path.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{DevicePath, PortMap};
+///
+/// let ports = PortMap::parse_for_host(r#"
+///     [ports]
+///     "1:1" = "front-left USB-C"
+///
+///     [host.labpc-07]
+///     "1:2" = "rear-top USB3"
+/// "#, Some("labpc-07")).unwrap();
+///
+/// let path: DevicePath = "1:2.4.1".parse().unwrap();
+/// let (port, name) = ports.physical_port(&path).unwrap();
+/// assert_eq!(port.to_string(), "1:2");
+/// assert_eq!(name, "rear-top USB3");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PortMap {
+    names: HashMap<DevicePath, String>,
+}
+
+impl PortMap {
+    /// Create an empty port map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Default location of the port map file
+    pub fn default_path() -> Option<std::path::PathBuf> {
+        config_dir().map(|dir| dir.join("ports.toml"))
+    }
+
+    /// Load the port map for this host from the default location
+    ///
+    /// Returns an empty map if the file does not exist.
+    pub fn load() -> Result<Self, PortMapError> {
+        match Self::default_path().map(Self::from_file) {
+            Some(Err(PortMapError::Io(e))) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::new())
+            }
+            Some(result) => result,
+            None => Ok(Self::new()),
+        }
+    }
+
+    /// Load the port map for this host from a TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PortMapError> {
+        let content = fs::read_to_string(path)?;
+        Self::parse_for_host(&content, hostname().as_deref())
+    }
+
+    /// Parse a port map file, applying the section for `host` on top of the shared entries
+    pub fn parse_for_host(s: &str, host: Option<&str>) -> Result<Self, PortMapError> {
+        let mut file: PortMapFile = toml::from_str(s)?;
+        let mut map = PortMap::new();
+
+        let host_entries = host.and_then(|h| file.host.remove(h)).unwrap_or_default();
+        for (path, name) in file.ports.into_iter().chain(host_entries) {
+            let parsed = path
+                .parse()
+                .map_err(|source| PortMapError::InvalidPath { path, source })?;
+            map.insert(parsed, name);
+        }
+        Ok(map)
+    }
+
+    /// Name a port
+    pub fn insert(&mut self, path: DevicePath, name: impl Into<String>) {
+        self.names.insert(path, name.into());
+    }
+
+    /// Get the name of exactly this port
+    pub fn name_of(&self, path: &DevicePath) -> Option<&str> {
+        self.names.get(path).map(|s| s.as_str())
+    }
+
+    /// Find the named port a device at `path` is plugged into
+    ///
+    /// Returns the port's path and name, walking up through any hubs.
+    pub fn physical_port(&self, path: &DevicePath) -> Option<(DevicePath, &str)> {
+        let mut current = Some(path.clone());
+        while let Some(p) = current {
+            if let Some(name) = self.name_of(&p) {
+                return Some((p, name));
+            }
+            current = p.parent();
+        }
+        None
+    }
+
+    /// Number of named ports
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Check if empty
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// Get the name of this machine
+pub fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|file| fs::read_to_string(file).ok())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORTS: &str = r#"
+        [ports]
+        "1:1" = "front-left USB-C"
+        "1:2" = "rear-top USB3"
+
+        [host.bench]
+        "1:2" = "rear-bottom USB3"
+    "#;
+
+    #[test]
+    fn test_host_section_overrides_shared() {
+        let path = DevicePath::new(1, vec![2]);
+
+        let other = PortMap::parse_for_host(PORTS, Some("other")).unwrap();
+        assert_eq!(other.name_of(&path), Some("rear-top USB3"));
+
+        let bench = PortMap::parse_for_host(PORTS, Some("bench")).unwrap();
+        assert_eq!(bench.name_of(&path), Some("rear-bottom USB3"));
+    }
+
+    #[test]
+    fn test_physical_port_walks_up() {
+        let ports = PortMap::parse_for_host(PORTS, None).unwrap();
+        let (port, name) = ports.physical_port(&DevicePath::new(1, vec![1, 3, 2])).unwrap();
+        assert_eq!(port, DevicePath::new(1, vec![1]));
+        assert_eq!(name, "front-left USB-C");
+        assert!(ports.physical_port(&DevicePath::new(2, vec![1])).is_none());
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        let err = PortMap::parse_for_host("[ports]\n\"x\" = \"y\"", None).unwrap_err();
+        assert!(matches!(err, PortMapError::InvalidPath { .. }));
+    }
+}
@@ -0,0 +1,334 @@
+//! Recording hotplug sessions to a file and replaying them
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::UsbDevice;
+use crate::error::{RecordingError, UsbTreeError};
+use crate::events::timestamp;
+use crate::live::{EventSource, TreeEvent};
+use crate::path::DevicePath;
+
+/// One line of a recording
+///
+/// A recording is JSON Lines: a snapshot of the connected devices first,
+/// then every event in the order it happened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Entry {
+    /// The devices connected when recording started
+    Snapshot {
+        #[serde(with = "timestamp")]
+        timestamp: SystemTime,
+        devices: Vec<UsbDevice>,
+    },
+    /// A device was connected
+    Attach {
+        #[serde(with = "timestamp")]
+        timestamp: SystemTime,
+        device: UsbDevice,
+    },
+    /// The device at a path was disconnected
+    Detach {
+        #[serde(with = "timestamp")]
+        timestamp: SystemTime,
+        path: DevicePath,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<u8>,
+    },
+}
+
+impl Entry {
+    fn event(timestamp: SystemTime, event: &TreeEvent) -> Self {
+        match event {
+            TreeEvent::Attached(device) => Entry::Attach {
+                timestamp,
+                device: device.clone(),
+            },
+            TreeEvent::Detached { path, address } => Entry::Detach {
+                timestamp,
+                path: path.clone(),
+                address: *address,
+            },
+        }
+    }
+}
+
+/// An event source that writes everything passing through it to a recording
+///
+/// Each entry is written as one line and flushed right away, so a
+/// recording cut short by Ctrl-C is complete up to that point. If writing
+/// fails, the event stream ends and [`Recorder::finish`] returns the error.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use usbinfo::{LiveTree, Recorder, SystemEvents};
+///
+/// let file = File::create("session.jsonl").unwrap();
+/// let mut recorder = Recorder::new(SystemEvents::new().unwrap(), file);
+/// futures_lite::future::block_on(LiveTree::new().run(&mut recorder)).unwrap();
+/// recorder.finish().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Recorder<S, W> {
+    source: S,
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<S, W: Write> Recorder<S, W> {
+    /// Record the events of `source` to `writer`
+    pub fn new(source: S, writer: W) -> Self {
+        Self {
+            source,
+            writer,
+            error: None,
+        }
+    }
+
+    /// Stop recording, returning the writer or the first write error
+    pub fn finish(self) -> io::Result<W> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.writer),
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = serde_json::to_writer(&mut self.writer, entry)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(self.writer))
+            .and_then(|()| self.writer.flush());
+        self.error = result.err();
+    }
+}
+
+impl<S: EventSource + Send, W: Write + Send> EventSource for Recorder<S, W> {
+    async fn snapshot(&mut self) -> Result<Vec<UsbDevice>, UsbTreeError> {
+        let devices = self.source.snapshot().await?;
+        self.write(&Entry::Snapshot {
+            timestamp: self.source.timestamp(),
+            devices: devices.clone(),
+        });
+        Ok(devices)
+    }
+
+    async fn next_event(&mut self) -> Option<TreeEvent> {
+        if self.error.is_some() {
+            return None;
+        }
+        let event = self.source.next_event().await?;
+        self.write(&Entry::event(self.source.timestamp(), &event));
+        Some(event)
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.source.timestamp()
+    }
+
+    fn speed(&self) -> f64 {
+        self.source.speed()
+    }
+}
+
+/// A recorded session, played back as an event source
+///
+/// The snapshot is returned right away; each event is delivered as long
+/// after it as it was recorded, divided by the replay speed.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{LiveTree, Replay};
+///
+/// let recording = r#"
+/// {"type":"snapshot","timestamp":"2026-01-05T10:00:00Z","devices":[]}
+/// {"type":"attach","timestamp":"2026-01-05T10:00:02Z","device":{"bus":1,"port_path":[2]}}
+/// {"type":"detach","timestamp":"2026-01-05T10:00:03Z","path":"1:2"}
+/// {"type":"attach","timestamp":"2026-01-05T10:00:03.5Z","device":{"bus":1,"port_path":[2]}}
+/// "#;
+/// let mut replay = Replay::from_reader(recording.as_bytes()).unwrap().with_speed(f64::INFINITY);
+///
+/// let live = LiveTree::new();
+/// futures_lite::future::block_on(live.run(&mut replay)).unwrap();
+/// assert!(live.read().get("1:2").is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Replay {
+    snapshot: Vec<UsbDevice>,
+    started: SystemTime,
+    events: VecDeque<(Duration, TreeEvent)>,
+    speed: f64,
+    start: Option<Instant>,
+    last: Duration,
+}
+
+impl Replay {
+    /// Load a recording from a file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Load a recording from JSON Lines, skipping blank lines
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, RecordingError> {
+        let mut entries = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line)
+                .map_err(|source| RecordingError::Parse { line: i + 1, source })?;
+            entries.push(entry);
+        }
+
+        let mut entries = entries.into_iter();
+        let Some(Entry::Snapshot { timestamp: started, devices }) = entries.next() else {
+            return Err(RecordingError::MissingSnapshot);
+        };
+        let offset = |timestamp: SystemTime| timestamp.duration_since(started).unwrap_or_default();
+        let events = entries
+            .filter_map(|entry| match entry {
+                Entry::Attach { timestamp, device } => {
+                    Some((offset(timestamp), TreeEvent::Attached(device)))
+                }
+                Entry::Detach { timestamp, path, address } => {
+                    Some((offset(timestamp), TreeEvent::Detached { path, address }))
+                }
+                // Snapshots after the first, e.g. from appending to a recording, are ignored
+                Entry::Snapshot { .. } => None,
+            })
+            .collect();
+
+        Ok(Self {
+            snapshot: devices,
+            started,
+            events,
+            speed: 1.0,
+            start: None,
+            last: Duration::ZERO,
+        })
+    }
+
+    /// Replay `speed` times as fast as recorded
+    ///
+    /// `f64::INFINITY` delivers every event without waiting.
+    ///
+    /// # Panics
+    ///
+    /// If `speed` is not positive.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        assert!(speed > 0.0, "replay speed must be positive");
+        self.speed = speed;
+        self
+    }
+
+    /// Number of events not yet delivered
+    pub fn remaining(&self) -> usize {
+        self.events.len()
+    }
+}
+
+impl EventSource for Replay {
+    async fn snapshot(&mut self) -> Result<Vec<UsbDevice>, UsbTreeError> {
+        self.start = Some(Instant::now());
+        Ok(self.snapshot.clone())
+    }
+
+    async fn next_event(&mut self) -> Option<TreeEvent> {
+        let (offset, event) = self.events.pop_front()?;
+        self.last = offset;
+        let start = *self.start.get_or_insert_with(Instant::now);
+        if self.speed.is_finite() {
+            // Wait relative to the start, so delays do not add up
+            let wait = (start + offset.div_f64(self.speed)).saturating_duration_since(Instant::now());
+            if !wait.is_zero() {
+                blocking::unblock(move || thread::sleep(wait)).await;
+            }
+        }
+        Some(event)
+    }
+
+    /// When the last event, or before any the snapshot, was recorded
+    fn timestamp(&self) -> SystemTime {
+        self.started + self.last
+    }
+
+    fn speed(&self) -> f64 {
+        self.speed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    const RECORDING: &str = r#"
+{"type":"snapshot","timestamp":"2026-01-05T10:00:00Z","devices":[{"bus":1,"port_path":[1],"class":9}]}
+{"type":"attach","timestamp":"2026-01-05T10:00:00.010Z","device":{"bus":1,"port_path":[1,2],"address":5,"speed":"full"}}
+{"type":"detach","timestamp":"2026-01-05T10:00:00.020Z","path":"1:1.2"}
+{"type":"attach","timestamp":"2026-01-05T10:00:00.030Z","device":{"bus":1,"port_path":[1,2],"address":6,"speed":"full"}}
+"#;
+
+    fn events(source: &mut impl EventSource) -> Vec<TreeEvent> {
+        block_on(async {
+            source.snapshot().await.unwrap();
+            let mut events = Vec::new();
+            while let Some(event) = source.next_event().await {
+                events.push(event);
+            }
+            events
+        })
+    }
+
+    #[test]
+    fn test_replay_paces_events() {
+        let mut replay = Replay::from_reader(RECORDING.as_bytes()).unwrap();
+        assert_eq!(replay.remaining(), 3);
+
+        let started = Instant::now();
+        let events = events(&mut replay);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(matches!(events[1], TreeEvent::Detached { ref path, .. } if path.to_string() == "1:1.2"));
+        assert!(matches!(events[2], TreeEvent::Attached(ref device) if device.address == 6));
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let replay = Replay::from_reader(RECORDING.as_bytes()).unwrap().with_speed(f64::INFINITY);
+        let mut recorder = Recorder::new(replay, Vec::new());
+        let recorded = events(&mut recorder);
+
+        let written = recorder.finish().unwrap();
+        let mut replay = Replay::from_reader(written.as_slice()).unwrap().with_speed(f64::INFINITY);
+        assert_eq!(block_on(replay.snapshot()).unwrap()[0].class, 9);
+        assert_eq!(events(&mut replay), recorded);
+
+        // Re-recording a replay keeps the original times
+        let last = humantime::format_rfc3339_millis(replay.timestamp()).to_string();
+        assert_eq!(last, "2026-01-05T10:00:00.030Z");
+    }
+
+    #[test]
+    fn test_invalid_recordings() {
+        let detach = r#"{"type":"detach","timestamp":"2026-01-05T10:00:00Z","path":"1:2"}"#;
+        let err = Replay::from_reader(detach.as_bytes()).unwrap_err();
+        assert!(matches!(err, RecordingError::MissingSnapshot));
+
+        let err = Replay::from_reader("\n{\"type\":\"bogus\"}".as_bytes()).unwrap_err();
+        assert!(matches!(err, RecordingError::Parse { line: 2, .. }));
+    }
+}
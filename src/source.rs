@@ -0,0 +1,116 @@
+//! Pluggable, asynchronous sources of USB devices
+
+use std::future::Future;
+
+use nusb::{DeviceId, MaybeFuture};
+
+use crate::device::UsbDevice;
+use crate::error::UsbTreeError;
+use crate::tree::UsbTree;
+
+/// Somewhere to enumerate USB devices from
+///
+/// The tree building functions are generic over the source, so the
+/// system's devices can be swapped for a fixed list in tests or for a
+/// recording.
+pub trait DeviceSource {
+    /// List the devices currently connected
+    fn list_devices(&self) -> impl Future<Output = Result<Vec<UsbDevice>, UsbTreeError>> + Send;
+}
+
+/// The devices connected to this machine, enumerated through nusb
+///
+/// Enumeration and the sysfs reads of each device run on a blocking
+/// thread pool, the latter concurrently for all devices, so listing never
+/// blocks the calling executor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemSource;
+
+impl DeviceSource for SystemSource {
+    async fn list_devices(&self) -> Result<Vec<UsbDevice>, UsbTreeError> {
+        let devices = system_devices().await?;
+        Ok(devices.into_iter().map(|(_, device)| device).collect())
+    }
+}
+
+/// Enumerate this machine's devices off the executor, with their nusb IDs
+///
+/// nusb probes each device's descriptors while its list is iterated, so
+/// the list is collected on the blocking pool too.
+pub(crate) async fn system_devices() -> Result<Vec<(DeviceId, UsbDevice)>, UsbTreeError> {
+    let infos = blocking::unblock(|| {
+        nusb::list_devices()
+            .wait()
+            .map(|infos| infos.collect::<Vec<_>>())
+            .map_err(|e| UsbTreeError::ListDevices(e.to_string()))
+    })
+    .await?;
+
+    // Spawn every read before awaiting any, so they run in parallel
+    let tasks: Vec<_> = infos
+        .into_iter()
+        .map(|info| blocking::unblock(move || (info.id(), UsbDevice::from_device_info(&info))))
+        .collect();
+
+    let mut devices = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        devices.push(task.await);
+    }
+    Ok(devices)
+}
+
+/// A fixed list of devices
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{StaticSource, UsbDevice, UsbTree};
+///
+/// let device = UsbDevice { bus: 1, port_path: vec![2], ..Default::default() };
+/// let source = StaticSource::new(vec![device]);
+/// let tree = futures_lite::future::block_on(UsbTree::from_source(&source)).unwrap();
+/// assert!(tree.get("1:2").is_some());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StaticSource {
+    devices: Vec<UsbDevice>,
+}
+
+impl StaticSource {
+    /// Create a source that always lists these devices
+    pub fn new(devices: Vec<UsbDevice>) -> Self {
+        Self { devices }
+    }
+}
+
+impl DeviceSource for StaticSource {
+    async fn list_devices(&self) -> Result<Vec<UsbDevice>, UsbTreeError> {
+        Ok(self.devices.clone())
+    }
+}
+
+impl<S: DeviceSource + Sync> DeviceSource for &S {
+    fn list_devices(&self) -> impl Future<Output = Result<Vec<UsbDevice>, UsbTreeError>> + Send {
+        (**self).list_devices()
+    }
+}
+
+impl UsbTree<UsbDevice> {
+    /// Build an indexed tree from the devices of a source
+    pub async fn from_source(source: &impl DeviceSource) -> Result<Self, UsbTreeError> {
+        let mut tree = UsbTree::new().with_index();
+        for device in source.list_devices().await? {
+            let path = device.path();
+            tree.insert_path(&path, device);
+        }
+        Ok(tree)
+    }
+}
+
+/// Build a UsbTree from the system's devices without blocking the executor
+///
+/// Works on any async runtime. The returned tree has its secondary indexes
+/// enabled, like the one from [`usb_tree`](crate::usb_tree).
+pub async fn usb_tree_async() -> Result<UsbTree<UsbDevice>, UsbTreeError> {
+    UsbTree::from_source(&SystemSource).await
+}
@@ -0,0 +1,97 @@
+//! Fixtures shared by the unit tests
+
+use nusb::Speed;
+
+use crate::device::UsbDevice;
+use crate::path::DevicePath;
+use crate::tree::UsbTree;
+
+/// Builds a [`UsbDevice`] for tests, starting from an empty device at a path
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceBuilder {
+    device: UsbDevice,
+}
+
+/// Start building a device at `path`, e.g. `"1:2.4"`
+pub(crate) fn device(path: &str) -> DeviceBuilder {
+    let path: DevicePath = path.parse().expect("valid device path");
+    DeviceBuilder {
+        device: UsbDevice {
+            bus: path.bus(),
+            port_path: path.ports().to_vec(),
+            ..Default::default()
+        },
+    }
+}
+
+/// Build a tree holding `devices` at their own paths
+pub(crate) fn tree(devices: impl IntoIterator<Item = UsbDevice>) -> UsbTree<UsbDevice> {
+    let mut tree = UsbTree::new();
+    for device in devices {
+        tree.insert_path(&device.path(), device);
+    }
+    tree
+}
+
+impl DeviceBuilder {
+    pub(crate) fn with_id(mut self, vid: u16, pid: u16) -> Self {
+        self.device.vid = vid;
+        self.device.pid = pid;
+        self
+    }
+
+    pub(crate) fn with_address(mut self, address: u8) -> Self {
+        self.device.address = address;
+        self
+    }
+
+    pub(crate) fn with_name(mut self, name: &str) -> Self {
+        self.device.name = name.to_string();
+        self
+    }
+
+    pub(crate) fn with_manufacturer(mut self, manufacturer: &str) -> Self {
+        self.device.manufacturer = Some(manufacturer.to_string());
+        self
+    }
+
+    pub(crate) fn with_product(mut self, product: &str) -> Self {
+        self.device.product = Some(product.to_string());
+        self
+    }
+
+    pub(crate) fn with_serial(mut self, serial: &str) -> Self {
+        self.device.serial = Some(serial.to_string());
+        self
+    }
+
+    pub(crate) fn with_class(mut self, class: u8) -> Self {
+        self.device.class = class;
+        self
+    }
+
+    pub(crate) fn with_interface_classes(mut self, classes: &[u8]) -> Self {
+        self.device.interface_classes = classes.to_vec();
+        self
+    }
+
+    pub(crate) fn with_speed(mut self, speed: Speed) -> Self {
+        self.device.speed = Some(speed);
+        self
+    }
+
+    pub(crate) fn with_bcd(mut self, usb: u16, device: u16) -> Self {
+        self.device.bcd_usb = usb;
+        self.device.bcd_device = device;
+        self
+    }
+
+    pub(crate) fn with_driver(mut self, driver: &str) -> Self {
+        self.device.drivers.push(driver.to_string());
+        self
+    }
+
+    pub(crate) fn build(self) -> UsbDevice {
+        self.device
+    }
+}
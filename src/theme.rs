@@ -0,0 +1,337 @@
+//! Color themes for tree output
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use colored::Color;
+use nusb::Speed;
+use serde::Deserialize;
+
+use crate::device::{parse_class, parse_speed, UsbDevice};
+use crate::error::ThemeError;
+
+/// When to emit ANSI colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ColorMode {
+    /// Color only when stdout is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
+
+impl ColorMode {
+    /// Resolve whether colors should be emitted
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => colored::control::ShouldColorize::from_env().should_colorize(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+
+    /// Apply this mode to the global `colored` override
+    ///
+    /// `colored` checks the environment itself when rendering, so forcing
+    /// colors into a pipe requires setting its override.
+    pub fn apply(self) {
+        match self {
+            ColorMode::Auto => colored::control::unset_override(),
+            ColorMode::Always => colored::control::set_override(true),
+            ColorMode::Never => colored::control::set_override(false),
+        }
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = ThemeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(ThemeError::InvalidColorMode(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for ColorMode {
+    type Error = ThemeError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorMode::Auto => "auto",
+            ColorMode::Always => "always",
+            ColorMode::Never => "never",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Which device property selects the color of a tree row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorBy {
+    /// Color by device class, or by the first interface class of devices
+    /// that declare theirs per interface
+    #[default]
+    Class,
+    /// Color by nesting depth
+    Depth,
+    /// Color by connection speed
+    Speed,
+    /// Color by vendor ID
+    Vendor,
+}
+
+/// Coloring rules for tree output
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{ColorBy, Theme};
+///
+/// let theme: Theme = r#"
+///     color_by = "class"
+///     highlight = "bright yellow"
+///
+///     [class]
+///     hub = "blue"
+///     hid = "green"
+/// "#.parse().unwrap();
+/// assert_eq!(theme.color_by, ColorBy::Class);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// Property used to pick a row color
+    pub color_by: ColorBy,
+    /// Color of bus header rows
+    pub bus: Color,
+    /// Colors cycled through by depth
+    pub depth: Vec<Color>,
+    /// Colors by device class
+    pub class: HashMap<u8, Color>,
+    /// Colors by connection speed
+    pub speed: HashMap<Speed, Color>,
+    /// Colors by vendor ID
+    pub vendor: HashMap<u16, Color>,
+    /// Color used when no rule matches
+    pub fallback: Color,
+    /// Color for highlighted devices
+    pub highlight: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            color_by: ColorBy::Class,
+            bus: Color::Red,
+            depth: vec![
+                Color::Red,
+                Color::Yellow,
+                Color::Green,
+                Color::Cyan,
+                Color::Blue,
+                Color::Magenta,
+                Color::BrightRed,
+                Color::BrightYellow,
+                Color::BrightGreen,
+                Color::BrightCyan,
+            ],
+            class: HashMap::from([
+                (0x01, Color::Magenta),
+                (0x02, Color::Cyan),
+                (0x03, Color::Green),
+                (0x08, Color::Yellow),
+                (0x09, Color::Blue),
+                (0x0e, Color::BrightMagenta),
+                (0xe0, Color::BrightCyan),
+                (0xef, Color::Yellow),
+                (0xff, Color::BrightRed),
+            ]),
+            speed: HashMap::from([
+                (Speed::Low, Color::Red),
+                (Speed::Full, Color::Yellow),
+                (Speed::High, Color::Green),
+                (Speed::Super, Color::Cyan),
+                (Speed::SuperPlus, Color::BrightCyan),
+            ]),
+            vendor: HashMap::new(),
+            fallback: Color::White,
+            highlight: Color::BrightYellow,
+        }
+    }
+}
+
+impl Theme {
+    /// Create the default theme, coloring rows by device class
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set which property selects the row color
+    pub fn with_color_by(mut self, color_by: ColorBy) -> Self {
+        self.color_by = color_by;
+        self
+    }
+
+    /// Load a theme from a TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ThemeError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Pick the color for a device row
+    pub fn device_color(&self, device: &UsbDevice, depth: usize) -> Color {
+        let color = match self.color_by {
+            ColorBy::Depth => return self.depth_color(depth),
+            ColorBy::Class => self.class.get(&device.function_class()),
+            ColorBy::Speed => device.speed.and_then(|s| self.speed.get(&s)),
+            ColorBy::Vendor => self.vendor.get(&device.vid),
+        };
+        color.copied().unwrap_or(self.fallback)
+    }
+
+    /// Pick the color for a given depth
+    pub fn depth_color(&self, depth: usize) -> Color {
+        if self.depth.is_empty() {
+            self.fallback
+        } else {
+            self.depth[depth % self.depth.len()]
+        }
+    }
+}
+
+/// On-disk theme representation, all fields optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeFile {
+    color_by: Option<ColorBy>,
+    bus: Option<String>,
+    depth: Option<Vec<String>>,
+    class: HashMap<String, String>,
+    speed: HashMap<String, String>,
+    vendor: HashMap<String, String>,
+    fallback: Option<String>,
+    highlight: Option<String>,
+}
+
+fn parse_color(s: &str) -> Result<Color, ThemeError> {
+    s.parse()
+        .map_err(|_| ThemeError::InvalidColor(s.to_string()))
+}
+
+impl FromStr for Theme {
+    type Err = ThemeError;
+
+    /// Parse a TOML theme; unspecified fields keep their default values
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let file: ThemeFile = toml::from_str(s)?;
+        let mut theme = Theme::default();
+
+        if let Some(color_by) = file.color_by {
+            theme.color_by = color_by;
+        }
+        if let Some(ref bus) = file.bus {
+            theme.bus = parse_color(bus)?;
+        }
+        if let Some(ref depth) = file.depth {
+            theme.depth = depth
+                .iter()
+                .map(|c| parse_color(c))
+                .collect::<Result<_, _>>()?;
+        }
+        for (class, color) in &file.class {
+            let code = parse_class(class).ok_or_else(|| ThemeError::InvalidClass(class.clone()))?;
+            theme.class.insert(code, parse_color(color)?);
+        }
+        for (speed, color) in &file.speed {
+            let speed_val =
+                parse_speed(speed).ok_or_else(|| ThemeError::InvalidSpeed(speed.clone()))?;
+            theme.speed.insert(speed_val, parse_color(color)?);
+        }
+        for (vendor, color) in &file.vendor {
+            let hex = vendor.strip_prefix("0x").unwrap_or(vendor);
+            let vid = u16::from_str_radix(hex, 16)
+                .map_err(|_| ThemeError::InvalidVendor(vendor.clone()))?;
+            theme.vendor.insert(vid, parse_color(color)?);
+        }
+        if let Some(ref fallback) = file.fallback {
+            theme.fallback = parse_color(fallback)?;
+        }
+        if let Some(ref highlight) = file.highlight {
+            theme.highlight = parse_color(highlight)?;
+        }
+
+        Ok(theme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::device;
+
+    #[test]
+    fn test_parse_theme() {
+        let theme: Theme = r#"
+            color_by = "speed"
+            bus = "bright white"
+            [speed]
+            high = "magenta"
+            [vendor]
+            "0x0483" = "cyan"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(theme.color_by, ColorBy::Speed);
+        assert_eq!(theme.bus, Color::BrightWhite);
+        assert_eq!(theme.speed[&Speed::High], Color::Magenta);
+        assert_eq!(theme.vendor[&0x0483], Color::Cyan);
+        // Unspecified rules keep their defaults
+        assert_eq!(theme.class[&0x09], Color::Blue);
+    }
+
+    #[test]
+    fn test_parse_class_names() {
+        let theme: Theme = "[class]\nhid = \"red\"\n\"0xef\" = \"green\"".parse().unwrap();
+        assert_eq!(theme.class[&0x03], Color::Red);
+        assert_eq!(theme.class[&0xef], Color::Green);
+    }
+
+    #[test]
+    fn test_invalid_color() {
+        let err = "bus = \"chartreuse\"".parse::<Theme>().unwrap_err();
+        assert!(matches!(err, ThemeError::InvalidColor(c) if c == "chartreuse"));
+    }
+
+    #[test]
+    fn test_class_color_of_interface_devices() {
+        let theme = Theme::default();
+        let keyboard = device("1:2").with_interface_classes(&[0x03]).build();
+        assert_eq!(theme.device_color(&keyboard, 0), Color::Green);
+        let hub = device("1:1").with_class(0x09).with_interface_classes(&[0x09]).build();
+        assert_eq!(theme.device_color(&hub, 0), Color::Blue);
+        let unknown = device("1:3").build();
+        assert_eq!(theme.device_color(&unknown, 0), theme.fallback);
+    }
+
+    #[test]
+    fn test_depth_color_wraps() {
+        let theme = Theme::default();
+        assert_eq!(theme.color_by, ColorBy::Class);
+        assert_eq!(theme.depth_color(0), theme.depth_color(theme.depth.len()));
+    }
+}
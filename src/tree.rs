@@ -1,27 +1,30 @@
 //! Tree data structures for USB device hierarchy
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use nusb::MaybeFuture;
+use futures_lite::future::block_on;
 
 use crate::device::UsbDevice;
 use crate::error::UsbTreeError;
-use crate::path::DevicePath;
+use crate::index::{text_matches, DeviceIndex, IndexKeys, Indexable};
+use crate::path::{DevicePath, DevicePathPattern};
+use crate::source::SystemSource;
+use crate::visit::{self, Order, Traverse, Visitor};
 
 /// A tree node for organizing port hierarchy
 #[derive(Debug, Clone)]
 pub struct PortTree<T> {
     /// Value stored at this node (if any)
     pub value: Option<T>,
-    /// Children indexed by port number
-    pub children: HashMap<u8, PortTree<T>>,
+    /// Children indexed by port number, in port order
+    pub children: BTreeMap<u8, PortTree<T>>,
 }
 
 impl<T> Default for PortTree<T> {
     fn default() -> Self {
         Self {
             value: None,
-            children: HashMap::new(),
+            children: BTreeMap::new(),
         }
     }
 }
@@ -39,7 +42,7 @@ impl<T> PortTree<T> {
         } else {
             self.children
                 .entry(ports[0])
-                .or_insert_with(PortTree::new)
+                .or_default()
                 .insert(&ports[1..], value);
         }
     }
@@ -53,19 +56,99 @@ impl<T> PortTree<T> {
         }
     }
 
-    /// Get all descendant values (including self)
-    pub fn descendants(&self) -> Vec<&T> {
-        let mut result = Vec::new();
-        if let Some(ref v) = self.value {
-            result.push(v);
+    /// Get the mutable subtree at the given port path
+    pub fn get_mut(&mut self, ports: &[u8]) -> Option<&mut PortTree<T>> {
+        if ports.is_empty() {
+            Some(self)
+        } else {
+            self.children.get_mut(&ports[0])?.get_mut(&ports[1..])
         }
-        for child in self.children.values() {
-            result.extend(child.descendants());
+    }
+
+    /// Take the value at the given port path, keeping any children
+    ///
+    /// Nodes left without a value or children are removed.
+    pub fn remove(&mut self, ports: &[u8]) -> Option<T> {
+        let value = self.get_mut(ports)?.value.take();
+        self.prune_path(ports);
+        value
+    }
+
+    /// Detach the subtree at the given port path
+    ///
+    /// An empty path detaches everything, leaving this node empty.
+    pub fn remove_subtree(&mut self, ports: &[u8]) -> Option<PortTree<T>> {
+        let Some((&last, parent)) = ports.split_last() else {
+            return Some(std::mem::take(self));
+        };
+        let removed = self.get_mut(parent)?.children.remove(&last);
+        self.prune_path(parent);
+        removed
+    }
+
+    /// Remove values for which the predicate returns false, keeping their children
+    pub fn retain(&mut self, mut predicate: impl FnMut(&T) -> bool) {
+        self.retain_with(&mut predicate);
+        self.prune_empty();
+    }
+
+    fn retain_with(&mut self, predicate: &mut impl FnMut(&T) -> bool) {
+        if self.value.as_ref().is_some_and(|value| !predicate(value)) {
+            self.value = None;
+        }
+        for child in self.children.values_mut() {
+            child.retain_with(predicate);
+        }
+    }
+
+    /// Remove all child nodes that hold no value in their subtree
+    pub fn prune_empty(&mut self) {
+        self.children.retain(|_, child| {
+            child.prune_empty();
+            !child.is_empty()
+        });
+    }
+
+    /// Remove empty nodes along a port path, deepest first
+    fn prune_path(&mut self, ports: &[u8]) {
+        if let Some((&first, rest)) = ports.split_first()
+            && let Some(child) = self.children.get_mut(&first)
+        {
+            child.prune_path(rest);
+            if child.is_empty() {
+                self.children.remove(&first);
+            }
         }
-        result
     }
 
-    /// Get direct children values
+    /// Check if this node has neither a value nor children
+    pub fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.is_empty()
+    }
+
+    /// Get all descendant values (including self) in depth-first port order
+    pub fn descendants(&self) -> Vec<&T> {
+        self.dfs(DevicePath::bus_only(0))
+            .map(|(_, _, value)| value)
+            .collect()
+    }
+
+    /// Iterate depth-first over this subtree, which sits at `path`
+    pub fn dfs(&self, path: DevicePath) -> Traverse<'_, T> {
+        Traverse::new([(path, self)], Order::DepthFirst)
+    }
+
+    /// Iterate breadth-first over this subtree, which sits at `path`
+    pub fn bfs(&self, path: DevicePath) -> Traverse<'_, T> {
+        Traverse::new([(path, self)], Order::BreadthFirst)
+    }
+
+    /// Walk this subtree, which sits at `path`, with a visitor
+    pub fn walk<V: Visitor<T>>(&self, path: DevicePath, visitor: &mut V) {
+        visit::walk(self, path, &Some, visitor);
+    }
+
+    /// Get direct children values in port order
     pub fn direct_children(&self) -> Vec<(u8, &T)> {
         self.children
             .iter()
@@ -75,29 +158,23 @@ impl<T> PortTree<T> {
 
     /// Get sorted child port numbers
     pub fn child_ports(&self) -> Vec<u8> {
-        let mut ports: Vec<u8> = self.children.keys().copied().collect();
-        ports.sort();
-        ports
-    }
-}
-
-/// Parse a port path string like "1.2.3" into a Vec<u8>
-fn parse_port_path(path: &str) -> Vec<u8> {
-    if path.is_empty() {
-        return vec![];
+        self.children.keys().copied().collect()
     }
-    path.split('.')
-        .filter_map(|s| s.parse::<u8>().ok())
-        .collect()
 }
 
 /// USB device tree with flat lookup and hierarchical structure
-#[derive(Debug)]
+///
+/// Devices are kept in path order: numerically by bus, then by port.
+#[derive(Debug, Clone)]
 pub struct UsbTree<T> {
     /// Flat map of path -> device
-    pub devices: HashMap<String, T>,
-    /// Hierarchical tree per bus: bus -> tree of keys
-    tree: HashMap<String, PortTree<String>>,
+    pub devices: BTreeMap<DevicePath, T>,
+    /// Hierarchical tree per bus: bus -> tree of paths
+    tree: BTreeMap<u8, PortTree<DevicePath>>,
+    /// Key function for the secondary indexes, if enabled
+    indexer: Option<fn(&T) -> IndexKeys>,
+    /// Secondary indexes, maintained while `indexer` is set
+    index: DeviceIndex,
 }
 
 impl<T> Default for UsbTree<T> {
@@ -110,19 +187,98 @@ impl<T> UsbTree<T> {
     /// Create a new empty UsbTree
     pub fn new() -> Self {
         Self {
-            devices: HashMap::new(),
-            tree: HashMap::new(),
+            devices: BTreeMap::new(),
+            tree: BTreeMap::new(),
+            indexer: None,
+            index: DeviceIndex::default(),
         }
     }
 
     /// Insert a device with a DevicePath
     pub fn insert_path(&mut self, path: &DevicePath, value: T) {
-        let key = path.to_string();
-        self.devices.insert(key.clone(), value);
+        if let Some(keys) = self.indexer {
+            self.index.insert(path, keys(&value));
+        }
+        self.devices.insert(path.clone(), value);
         self.tree
-            .entry(path.bus_str())
+            .entry(path.bus())
             .or_default()
-            .insert(path.ports(), key);
+            .insert(path.ports(), path.clone());
+    }
+
+    /// Insert a device, returning the one previously at that path
+    pub fn replace(&mut self, path: &DevicePath, value: T) -> Option<T> {
+        let previous = self.devices.remove(path);
+        self.insert_path(path, value);
+        previous
+    }
+
+    /// Remove the device at `path`
+    ///
+    /// Devices below it stay in the tree, reachable through an empty hub node.
+    pub fn remove(&mut self, path: &DevicePath) -> Option<T> {
+        let value = self.devices.remove(path)?;
+        self.index.remove(path);
+        if let Some(bus) = self.tree.get_mut(&path.bus()) {
+            bus.remove(path.ports());
+        }
+        self.remove_bus_if_empty(path.bus());
+        Some(value)
+    }
+
+    /// Remove the device at `path` and everything below it
+    ///
+    /// Returns the removed devices in path order.
+    pub fn remove_subtree(&mut self, path: &DevicePath) -> Vec<(DevicePath, T)> {
+        let Some(node) = self
+            .tree
+            .get_mut(&path.bus())
+            .and_then(|bus| bus.remove_subtree(path.ports()))
+        else {
+            return vec![];
+        };
+        self.remove_bus_if_empty(path.bus());
+
+        node.dfs(path.clone())
+            .filter_map(|(_, _, key)| {
+                self.index.remove(key);
+                self.devices.remove_entry(key)
+            })
+            .collect()
+    }
+
+    /// Keep only the devices for which the predicate returns true
+    ///
+    /// Devices below a removed hub are kept if they match; the hub's node
+    /// stays as an empty link. Empty branches are pruned.
+    pub fn retain(&mut self, mut predicate: impl FnMut(&DevicePath, &T) -> bool) {
+        let index = &mut self.index;
+        self.devices.retain(|path, value| {
+            let keep = predicate(path, value);
+            if !keep {
+                index.remove(path);
+            }
+            keep
+        });
+        let devices = &self.devices;
+        for bus in self.tree.values_mut() {
+            bus.retain(|key| devices.contains_key(key));
+        }
+        self.prune_empty();
+    }
+
+    /// Remove tree nodes and buses that hold no device
+    pub fn prune_empty(&mut self) {
+        self.tree.retain(|_, bus| {
+            bus.prune_empty();
+            !bus.is_empty()
+        });
+    }
+
+    fn remove_bus_if_empty(&mut self, bus: u8) {
+        if self.tree.get(&bus).is_some_and(PortTree::is_empty) {
+            self.tree.remove(&bus);
+        }
     }
 
     /// Insert with bus_id and port_chain (convenience method)
@@ -134,20 +290,12 @@ impl<T> UsbTree<T> {
 
     /// Get device by DevicePath
     pub fn get_by_path(&self, path: &DevicePath) -> Option<&T> {
-        self.devices.get(&path.to_string())
+        self.devices.get(path)
     }
 
     /// Get device by path string "bus:port.path" e.g., "1:1.2.3"
     pub fn get(&self, path: &str) -> Option<&T> {
-        // Try direct lookup first
-        if let Some(device) = self.devices.get(path) {
-            return Some(device);
-        }
-        // Try parsing and looking up
-        if let Ok(parsed) = path.parse::<DevicePath>() {
-            return self.devices.get(&parsed.to_string());
-        }
-        None
+        self.get_by_path(&path.parse().ok()?)
     }
 
     /// Get device by path, returning an error if not found
@@ -163,65 +311,223 @@ impl<T> UsbTree<T> {
     }
 
     /// Get mutable device by path string
+    ///
+    /// The secondary indexes do not see changes made through the returned
+    /// reference until [`UsbTree::build_index`] is called again.
     pub fn get_mut(&mut self, path: &str) -> Option<&mut T> {
-        self.devices.get_mut(path)
+        self.get_mut_by_path(&path.parse().ok()?)
     }
 
     /// Get mutable device by DevicePath
+    ///
+    /// See [`UsbTree::get_mut`] about the secondary indexes.
     pub fn get_mut_by_path(&mut self, path: &DevicePath) -> Option<&mut T> {
-        self.devices.get_mut(&path.to_string())
+        self.devices.get_mut(path)
     }
 
-    /// Get all devices under a subtree by DevicePath
+    /// Get all devices under a subtree by DevicePath, in path order
     pub fn get_subtree_by_path(&self, path: &DevicePath) -> Vec<&T> {
+        self.dfs_from(path).map(|(_, _, device)| device).collect()
+    }
+
+    /// Get all devices under a subtree, e.g., "1:1.2" or "1"
+    pub fn get_subtree(&self, path: &str) -> Vec<&T> {
+        let parsed = path
+            .parse::<DevicePath>()
+            .ok()
+            .or_else(|| path.parse().ok().map(DevicePath::bus_only));
+        parsed
+            .map(|p| self.get_subtree_by_path(&p))
+            .unwrap_or_default()
+    }
+
+    /// Get all devices whose path matches a pattern, with their paths
+    pub fn select(&self, pattern: &DevicePathPattern) -> Vec<(DevicePath, &T)> {
+        self.devices
+            .iter()
+            .filter(|(path, _)| pattern.matches(path))
+            .map(|(path, device)| (path.clone(), device))
+            .collect()
+    }
+
+    /// Iterate depth-first over all devices, yielding `(path, depth, device)`
+    ///
+    /// Root hubs are at depth 0.
+    pub fn dfs(&self) -> impl Iterator<Item = (DevicePath, usize, &T)> {
+        self.traverse(self.roots(), Order::DepthFirst)
+    }
+
+    /// Iterate breadth-first over all devices, yielding `(path, depth, device)`
+    pub fn bfs(&self) -> impl Iterator<Item = (DevicePath, usize, &T)> {
+        self.traverse(self.roots(), Order::BreadthFirst)
+    }
+
+    /// Iterate depth-first over the subtree at `path`, depth counted from `path`
+    pub fn dfs_from(&self, path: &DevicePath) -> impl Iterator<Item = (DevicePath, usize, &T)> {
+        self.traverse(self.node(path).map(|node| (path.clone(), node)), Order::DepthFirst)
+    }
+
+    /// Iterate breadth-first over the subtree at `path`, depth counted from `path`
+    pub fn bfs_from(&self, path: &DevicePath) -> impl Iterator<Item = (DevicePath, usize, &T)> {
+        self.traverse(self.node(path).map(|node| (path.clone(), node)), Order::BreadthFirst)
+    }
+
+    /// Walk every bus with a visitor, starting each at its root hub
+    pub fn walk<V: Visitor<T>>(&self, visitor: &mut V) {
+        for (path, node) in self.roots() {
+            visit::walk(node, path, &|key| self.devices.get(key), visitor);
+        }
+    }
+
+    /// Walk the subtree at `path` with a visitor
+    ///
+    /// Returns `false` if there is no such subtree or the visitor skipped it.
+    pub fn walk_from<V: Visitor<T>>(&self, path: &DevicePath, visitor: &mut V) -> bool {
+        self.node(path).is_some_and(|node| {
+            visit::walk(node, path.clone(), &|key| self.devices.get(key), visitor)
+        })
+    }
+
+    fn roots(&self) -> Vec<(DevicePath, &PortTree<DevicePath>)> {
         self.tree
-            .get(&path.bus_str())
-            .and_then(|t| t.get(path.ports()))
-            .map(|node| {
-                node.descendants()
-                    .into_iter()
-                    .filter_map(|key| self.devices.get(key))
-                    .collect()
-            })
+            .iter()
+            .map(|(&bus, node)| (DevicePath::bus_only(bus), node))
+            .collect()
+    }
+
+    fn node(&self, path: &DevicePath) -> Option<&PortTree<DevicePath>> {
+        self.tree.get(&path.bus())?.get(path.ports())
+    }
+
+    fn traverse<'t>(
+        &'t self,
+        roots: impl IntoIterator<Item = (DevicePath, &'t PortTree<DevicePath>)>,
+        order: Order,
+    ) -> impl Iterator<Item = (DevicePath, usize, &'t T)> {
+        Traverse::new(roots, order)
+            .filter_map(|(path, depth, key)| self.devices.get(key).map(|device| (path, depth, device)))
+    }
+
+    /// Get the nearest device above `path`, usually the hub it is plugged into
+    pub fn parent_of(&self, path: &DevicePath) -> Option<(DevicePath, &T)> {
+        self.ancestors_of(path).into_iter().next()
+    }
+
+    /// Get the devices plugged directly into `path`, in port order
+    pub fn children_of(&self, path: &DevicePath) -> Vec<(DevicePath, &T)> {
+        self.node(path)
+            .map(|node| self.node_values(node.children.values()))
             .unwrap_or_default()
     }
 
-    /// Get all devices under a subtree, e.g., "1:1.2"
-    pub fn get_subtree(&self, path: &str) -> Vec<&T> {
-        if let Ok(parsed) = path.parse::<DevicePath>() {
-            self.get_subtree_by_path(&parsed)
-        } else {
-            // Fallback to old parsing for backwards compatibility
-            let (bus, port_path) = path.split_once(':').unwrap_or((path, ""));
-            let ports = parse_port_path(port_path);
-
-            self.tree
-                .get(bus)
-                .and_then(|t| t.get(&ports))
-                .map(|node| {
-                    node.descendants()
-                        .into_iter()
-                        .filter_map(|key| self.devices.get(key))
-                        .collect()
+    /// Get the other devices on the same hub as `path`, in port order
+    pub fn siblings_of(&self, path: &DevicePath) -> Vec<(DevicePath, &T)> {
+        let Some(hub) = path.parent() else {
+            return vec![];
+        };
+        self.children_of(&hub)
+            .into_iter()
+            .filter(|(sibling, _)| sibling != path)
+            .collect()
+    }
+
+    /// Get the hub chain above `path`, nearest first, ending at the root hub
+    pub fn ancestors_of(&self, path: &DevicePath) -> Vec<(DevicePath, &T)> {
+        std::iter::successors(path.parent(), DevicePath::parent)
+            .filter_map(|p| self.devices.get(&p).map(|device| (p, device)))
+            .collect()
+    }
+
+    /// Get the deepest device that is `a`, `b` or an ancestor of both
+    ///
+    /// Two devices share a hub when this is the parent of both. Returns
+    /// `None` for devices on different buses.
+    pub fn common_ancestor(&self, a: &DevicePath, b: &DevicePath) -> Option<(DevicePath, &T)> {
+        let prefix = a.common_prefix(b)?;
+        std::iter::successors(Some(prefix), DevicePath::parent)
+            .find_map(|p| self.devices.get(&p).map(|device| (p, device)))
+    }
+
+    fn node_values<'t>(
+        &self,
+        nodes: impl Iterator<Item = &'t PortTree<DevicePath>>,
+    ) -> Vec<(DevicePath, &T)> {
+        nodes
+            .filter_map(|node| node.value.as_ref())
+            .filter_map(|p| self.devices.get(p).map(|device| (p.clone(), device)))
+            .collect()
+    }
+
+    /// Transform every device, keeping the tree structure
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> UsbTree<U> {
+        self.map_with_path(|_, value| f(value))
+    }
+
+    /// Transform every device given its path, keeping the tree structure
+    ///
+    /// The result has no secondary indexes.
+    pub fn map_with_path<U>(self, mut f: impl FnMut(&DevicePath, T) -> U) -> UsbTree<U> {
+        UsbTree {
+            devices: self
+                .devices
+                .into_iter()
+                .map(|(path, value)| {
+                    let mapped = f(&path, value);
+                    (path, mapped)
                 })
-                .unwrap_or_default()
+                .collect(),
+            tree: self.tree,
+            indexer: None,
+            index: DeviceIndex::default(),
         }
     }
 
-    /// Get all bus IDs
-    pub fn buses(&self) -> Vec<&str> {
-        let mut buses: Vec<&str> = self.tree.keys().map(|s| s.as_str()).collect();
-        buses.sort();
-        buses
+    /// Transform every device, stopping at the first error
+    pub fn try_map<U, E>(self, mut f: impl FnMut(T) -> Result<U, E>) -> Result<UsbTree<U>, E> {
+        Ok(UsbTree {
+            devices: self
+                .devices
+                .into_iter()
+                .map(|(path, value)| f(value).map(|mapped| (path, mapped)))
+                .collect::<Result<_, E>>()?,
+            tree: self.tree,
+            indexer: None,
+            index: DeviceIndex::default(),
+        })
+    }
+
+    /// Pair up devices present at the same path in both trees
+    ///
+    /// Devices found in only one of the trees are dropped.
+    pub fn zip<U>(self, mut other: UsbTree<U>) -> UsbTree<(T, U)> {
+        let mut zipped = UsbTree::new();
+        for (path, value) in self.devices {
+            if let Some(other_value) = other.devices.remove(&path) {
+                zipped.insert_path(&path, (value, other_value));
+            }
+        }
+        zipped
+    }
+
+    /// Attach the device at the same path in `other`, if any, to every device
+    ///
+    /// Keeps the structure of this tree; devices only in `other` are dropped.
+    pub fn join<U>(self, mut other: UsbTree<U>) -> UsbTree<(T, Option<U>)> {
+        self.map_with_path(|path, value| (value, other.devices.remove(path)))
+    }
+
+    /// Get all bus numbers in ascending order
+    pub fn buses(&self) -> Vec<u8> {
+        self.tree.keys().copied().collect()
     }
 
     /// Get the PortTree for a specific bus
-    pub fn bus_tree(&self, bus: &str) -> Option<&PortTree<String>> {
-        self.tree.get(bus)
+    pub fn bus_tree(&self, bus: u8) -> Option<&PortTree<DevicePath>> {
+        self.tree.get(&bus)
     }
 
-    /// Get all devices (flat iterator)
-    pub fn all_devices(&self) -> impl Iterator<Item = (&String, &T)> {
+    /// Get all devices in path order
+    pub fn all_devices(&self) -> impl Iterator<Item = (&DevicePath, &T)> {
         self.devices.iter()
     }
 
@@ -236,6 +542,99 @@ impl<T> UsbTree<T> {
     }
 }
 
+impl<T: Indexable> UsbTree<T> {
+    /// Enable the secondary indexes, building them from the current devices
+    ///
+    /// Once enabled, the indexes are kept up to date on insert and remove.
+    pub fn build_index(&mut self) {
+        self.indexer = Some(T::index_keys);
+        self.index = DeviceIndex::default();
+        for (path, value) in &self.devices {
+            self.index.insert(path, value.index_keys());
+        }
+    }
+
+    /// Enable the secondary indexes (builder form of [`UsbTree::build_index`])
+    pub fn with_index(mut self) -> Self {
+        self.build_index();
+        self
+    }
+
+    /// Check if the secondary indexes are enabled
+    pub fn is_indexed(&self) -> bool {
+        self.indexer.is_some()
+    }
+
+    /// Find the device with a serial number (the first in path order if several share it)
+    ///
+    /// Uses the index if enabled, otherwise scans all devices.
+    pub fn find_by_serial(&self, serial: &str) -> Option<(DevicePath, &T)> {
+        if self.is_indexed() {
+            return self.index.serial(serial).and_then(|path| self.entry(path));
+        }
+        self.scan(|keys| keys.serial.as_deref() == Some(serial)).into_iter().next()
+    }
+
+    /// Find all devices with a VID:PID, in path order
+    pub fn find_by_vid_pid(&self, vid: u16, pid: u16) -> Vec<(DevicePath, &T)> {
+        if self.is_indexed() {
+            return self
+                .index
+                .vid_pid(vid, pid)
+                .filter_map(|path| self.entry(path))
+                .collect();
+        }
+        self.scan(|keys| keys.vid_pid == (vid, pid))
+    }
+
+    /// Find the device with a bus number and device address
+    pub fn find_by_bus_address(&self, bus: u8, address: u8) -> Option<(DevicePath, &T)> {
+        if self.is_indexed() {
+            return self
+                .index
+                .bus_address(bus, address)
+                .and_then(|path| self.entry(path));
+        }
+        self.scan(|keys| keys.bus_address == (bus, address)).into_iter().next()
+    }
+
+    /// Find devices whose manufacturer or product contain every word of `query`
+    ///
+    /// Matching ignores case and word order, so `"stm virtual"` finds an
+    /// "STMicroelectronics STM32 Virtual ComPort".
+    pub fn search(&self, query: &str) -> Vec<(DevicePath, &T)> {
+        if self.is_indexed() {
+            return self
+                .index
+                .search(query)
+                .into_iter()
+                .filter_map(|path| self.entry(path))
+                .collect();
+        }
+        self.scan(|keys| text_matches(&keys.text.to_lowercase(), query))
+    }
+
+    fn entry(&self, path: &DevicePath) -> Option<(DevicePath, &T)> {
+        self.devices.get(path).map(|value| (path.clone(), value))
+    }
+
+    /// Find devices by key without the index
+    fn scan(&self, predicate: impl Fn(&IndexKeys) -> bool) -> Vec<(DevicePath, &T)> {
+        self.devices
+            .iter()
+            .filter(|(_, value)| predicate(&value.index_keys()))
+            .map(|(path, value)| (path.clone(), value))
+            .collect()
+    }
+}
+
+impl UsbTree<UsbDevice> {
+    /// Get the hub a device is plugged into
+    pub fn hub_of(&self, device: &UsbDevice) -> Option<(DevicePath, &UsbDevice)> {
+        self.parent_of(&device.path())
+    }
+}
+
 impl<T> std::ops::Index<&str> for UsbTree<T> {
     type Output = T;
 
@@ -253,19 +652,189 @@ impl<T> std::ops::Index<&DevicePath> for UsbTree<T> {
 }
 
 /// Build a UsbTree from actual system devices using nusb
+///
+/// The returned tree has its secondary indexes enabled. This blocks the
+/// calling thread; see [`usb_tree_async`](crate::usb_tree_async).
 pub fn usb_tree() -> Result<UsbTree<UsbDevice>, UsbTreeError> {
-    let devices: Vec<nusb::DeviceInfo> = nusb::list_devices()
-        .wait()
-        .map_err(|e| UsbTreeError::ListDevices(e.to_string()))?
-        .collect();
+    block_on(UsbTree::from_source(&SystemSource))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn tree() -> UsbTree<&'static str> {
+        let mut tree = UsbTree::new();
+        for (path, name) in [
+            ("1:", "root"),
+            ("1:1", "hub"),
+            ("1:1.2", "sub-hub"),
+            ("1:1.2.1", "a"),
+            ("1:1.2.3", "b"),
+            ("1:1.4", "c"),
+            ("2:1", "other-bus"),
+        ] {
+            tree.insert_path(&path.parse().unwrap(), name);
+        }
+        tree
+    }
+
+    fn path(s: &str) -> DevicePath {
+        s.parse().unwrap()
+    }
+
+    fn names<'a>(items: Vec<(DevicePath, &&'a str)>) -> Vec<&'a str> {
+        items.into_iter().map(|(_, name)| *name).collect()
+    }
+
+    fn paths(tree: &UsbTree<&str>) -> Vec<String> {
+        tree.dfs().map(|(path, _, _)| path.to_string()).collect()
+    }
+
+    #[test]
+    fn test_remove_keeps_children() {
+        let mut tree = tree();
+        assert_eq!(tree.remove(&path("1:1.2")), Some("sub-hub"));
+        assert_eq!(tree.remove(&path("1:1.2")), None);
+        assert_eq!(names(tree.children_of(&path("1:1.2"))), ["a", "b"]);
+
+        tree.remove(&path("1:1.2.1"));
+        tree.remove(&path("1:1.2.3"));
+        assert!(tree.bus_tree(1).unwrap().get(&[1, 2]).is_none());
+
+        tree.remove(&path("2:1"));
+        assert_eq!(tree.buses(), [1]);
+    }
+
+    #[test]
+    fn test_remove_subtree() {
+        let mut tree = tree();
+        let removed: Vec<String> = tree
+            .remove_subtree(&path("1:1.2"))
+            .into_iter()
+            .map(|(path, _)| path.to_string())
+            .collect();
+        assert_eq!(removed, ["1:1.2", "1:1.2.1", "1:1.2.3"]);
+        assert_eq!(paths(&tree), ["1:", "1:1", "1:1.4", "2:1"]);
+        assert_eq!(tree.len(), 4);
+    }
+
+    #[test]
+    fn test_retain_and_prune() {
+        let mut tree = tree();
+        tree.retain(|_, name| name.len() == 1);
+        assert_eq!(paths(&tree), ["1:1.2.1", "1:1.2.3", "1:1.4"]);
+        assert_eq!(tree.buses(), [1]);
+
+        let mut ports = PortTree::new();
+        ports.insert(&[1, 1], "x");
+        ports.insert(&[2], "y");
+        ports.get_mut(&[1, 1]).unwrap().value = None;
+        ports.prune_empty();
+        assert_eq!(ports.child_ports(), [2]);
+    }
+
+    #[test]
+    fn test_map_zip_join() {
+        let lengths = tree().map(str::len);
+        assert_eq!(lengths.get("1:1.2"), Some(&7));
+        assert_eq!(lengths.children_of(&path("1:1")).len(), 2);
+
+        let with_paths = tree().map_with_path(|path, name| format!("{}={}", path, name));
+        assert_eq!(with_paths.get("2:1").map(String::as_str), Some("2:1=other-bus"));
+
+        let failed: Result<UsbTree<usize>, &str> =
+            tree().try_map(|name| if name == "c" { Err(name) } else { Ok(name.len()) });
+        assert_eq!(failed.unwrap_err(), "c");
+
+        let mut tags = UsbTree::new();
+        tags.insert_path(&path("1:1.4"), 42);
+        tags.insert_path(&path("3:1"), 7);
+
+        let zipped = tree().zip(tags);
+        assert_eq!(paths(&zipped.map(|(name, _)| name)), ["1:1.4"]);
+
+        let mut tags = UsbTree::new();
+        tags.insert_path(&path("1:1.4"), 42);
+        let joined = tree().join(tags);
+        assert_eq!(joined.len(), 7);
+        assert_eq!(joined.get("1:1.4"), Some(&("c", Some(42))));
+        assert_eq!(joined.get("1:1"), Some(&("hub", None)));
+    }
+
+    /// An STMicroelectronics device on port `address` of bus 1
+    fn stm(serial: &str, vid: u16, address: u8, product: &str) -> UsbDevice {
+        test_support::device(&format!("1:{}", address))
+            .with_id(vid, 0x5740)
+            .with_address(address)
+            .with_manufacturer("STMicroelectronics")
+            .with_product(product)
+            .with_serial(serial)
+            .build()
+    }
+
+    #[test]
+    fn test_index_maintained_on_insert_and_remove() {
+        let mut tree = UsbTree::new().with_index();
+        for dev in [
+            stm("A1", 0x0483, 2, "STM32 Virtual ComPort"),
+            stm("B2", 0x0483, 3, "STM32 STLink"),
+            stm("C3", 0x1234, 4, "Other"),
+        ] {
+            tree.insert_path(&dev.path(), dev);
+        }
 
-    let mut tree = UsbTree::new();
+        assert_eq!(tree.find_by_serial("B2").map(|(p, _)| p), Some(path("1:3")));
+        assert_eq!(tree.find_by_vid_pid(0x0483, 0x5740).len(), 2);
+        assert_eq!(tree.find_by_bus_address(1, 4).map(|(p, _)| p), Some(path("1:4")));
+        assert_eq!(tree.search("stm virtual").len(), 1);
+        assert_eq!(tree.search("stm").len(), 3);
 
-    for info in &devices {
-        let device = UsbDevice::from_device_info(info);
-        let path = device.path();
-        tree.insert_path(&path, device);
+        tree.remove(&path("1:3"));
+        assert!(tree.find_by_serial("B2").is_none());
+        assert_eq!(tree.find_by_vid_pid(0x0483, 0x5740).len(), 1);
+
+        tree.replace(&path("1:2"), stm("D4", 0x0483, 2, "Replaced"));
+        assert!(tree.find_by_serial("A1").is_none());
+        assert!(tree.find_by_serial("D4").is_some());
+
+        tree.retain(|_, dev| dev.vid != 0x1234);
+        assert!(tree.find_by_bus_address(1, 4).is_none());
     }
 
-    Ok(tree)
+    #[test]
+    fn test_lookups_without_index() {
+        let mut tree = UsbTree::new();
+        let dev = stm("A1", 0x0483, 2, "STM32 Virtual ComPort");
+        tree.insert_path(&dev.path(), dev);
+
+        assert!(!tree.is_indexed());
+        assert!(tree.find_by_serial("A1").is_some());
+        assert_eq!(tree.search("virtual COMPORT").len(), 1);
+    }
+
+    #[test]
+    fn test_parent_children_siblings() {
+        let tree = tree();
+        assert_eq!(tree.parent_of(&path("1:1.2.1")).map(|(_, n)| *n), Some("sub-hub"));
+        assert_eq!(tree.parent_of(&path("1:1")).map(|(_, n)| *n), Some("root"));
+        assert_eq!(tree.parent_of(&path("1:")), None);
+        assert_eq!(names(tree.children_of(&path("1:1"))), ["sub-hub", "c"]);
+        assert_eq!(names(tree.siblings_of(&path("1:1.2.3"))), ["a"]);
+    }
+
+    #[test]
+    fn test_ancestors_and_common_ancestor() {
+        let tree = tree();
+        assert_eq!(names(tree.ancestors_of(&path("1:1.2.3"))), ["sub-hub", "hub", "root"]);
+        // Missing intermediate hubs are skipped
+        assert_eq!(names(tree.ancestors_of(&path("2:1"))), Vec::<&str>::new());
+
+        let common = |a, b| tree.common_ancestor(&path(a), &path(b)).map(|(p, _)| p.to_string());
+        assert_eq!(common("1:1.2.1", "1:1.2.3"), Some("1:1.2".to_string()));
+        assert_eq!(common("1:1.2.1", "1:1.4"), Some("1:1".to_string()));
+        assert_eq!(common("1:1.2", "1:1.2.3"), Some("1:1.2".to_string()));
+        assert_eq!(common("1:1.2.1", "2:1"), None);
+    }
 }
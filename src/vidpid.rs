@@ -0,0 +1,305 @@
+//! Wildcard, range and masked VID:PID matching
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::device::UsbDevice;
+use crate::error::VidPidError;
+
+/// Matcher for a single 16-bit vendor or product ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdMatch {
+    /// Any ID (`*`)
+    Any,
+    /// Exactly this ID (`0483`)
+    Exact(u16),
+    /// Inclusive range (`0100-01ff`)
+    Range(u16, u16),
+    /// IDs equal to `value` in the bits set in `mask` (`5600/ff00`)
+    Mask {
+        /// Expected bits
+        value: u16,
+        /// Bits to compare
+        mask: u16,
+    },
+}
+
+impl IdMatch {
+    /// Check whether an ID matches
+    pub fn matches(self, id: u16) -> bool {
+        match self {
+            IdMatch::Any => true,
+            IdMatch::Exact(value) => id == value,
+            IdMatch::Range(lo, hi) => (lo..=hi).contains(&id),
+            IdMatch::Mask { value, mask } => id & mask == value & mask,
+        }
+    }
+
+    fn parse(s: &str, input: &str, part: &'static str) -> Result<Self, VidPidError> {
+        let invalid = || VidPidError::InvalidId {
+            input: input.to_string(),
+            part,
+            value: s.to_string(),
+        };
+        // from_str_radix alone would accept a sign, as in "+403"
+        let hex = |h: &str| {
+            let h = h.strip_prefix("0x").unwrap_or(h);
+            if !h.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            u16::from_str_radix(h, 16).map_err(|_| invalid())
+        };
+
+        if s == "*" {
+            Ok(IdMatch::Any)
+        } else if let Some((lo, hi)) = s.split_once('-') {
+            let (lo, hi) = (hex(lo)?, hex(hi)?);
+            if lo > hi {
+                return Err(VidPidError::EmptyRange {
+                    input: input.to_string(),
+                    value: s.to_string(),
+                });
+            }
+            Ok(IdMatch::Range(lo, hi))
+        } else if let Some((value, mask)) = s.split_once('/') {
+            Ok(IdMatch::Mask {
+                value: hex(value)?,
+                mask: hex(mask)?,
+            })
+        } else {
+            hex(s).map(IdMatch::Exact)
+        }
+    }
+}
+
+impl fmt::Display for IdMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdMatch::Any => write!(f, "*"),
+            IdMatch::Exact(id) => write!(f, "{:04x}", id),
+            IdMatch::Range(lo, hi) => write!(f, "{:04x}-{:04x}", lo, hi),
+            IdMatch::Mask { value, mask } => write!(f, "{:04x}/{:04x}", value, mask),
+        }
+    }
+}
+
+/// A single VID:PID pattern, optionally negated with a leading `!`
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::VidPidPattern;
+///
+/// let ftdi: VidPidPattern = "0403:*".parse().unwrap();
+/// assert!(ftdi.matches_ids(0x0403, 0x6001));
+///
+/// let range: VidPidPattern = "0483:5700-57ff".parse().unwrap();
+/// assert!(range.matches_ids(0x0483, 0x5740));
+///
+/// let masked: VidPidPattern = "2e8a:0000/ff00".parse().unwrap();
+/// assert!(masked.matches_ids(0x2e8a, 0x0003));
+///
+/// let err = "0483:57zz".parse::<VidPidPattern>().unwrap_err();
+/// assert!(err.to_string().contains("57zz"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct VidPidPattern {
+    /// Vendor ID matcher
+    pub vid: IdMatch,
+    /// Product ID matcher
+    pub pid: IdMatch,
+    /// Whether this pattern excludes matching devices
+    pub negated: bool,
+}
+
+impl VidPidPattern {
+    /// Check whether a VID:PID pair matches, ignoring negation
+    pub fn matches_ids(&self, vid: u16, pid: u16) -> bool {
+        self.vid.matches(vid) && self.pid.matches(pid)
+    }
+
+    /// Check whether a device matches, ignoring negation
+    pub fn matches(&self, device: &UsbDevice) -> bool {
+        self.matches_ids(device.vid, device.pid)
+    }
+}
+
+impl TryFrom<String> for VidPidPattern {
+    type Error = VidPidError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for VidPidPattern {
+    type Err = VidPidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        let (negated, body) = match input.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+        let (vid, pid) = body
+            .split_once(':')
+            .ok_or_else(|| VidPidError::MissingColon(input.to_string()))?;
+
+        Ok(VidPidPattern {
+            vid: IdMatch::parse(vid, input, "VID")?,
+            pid: IdMatch::parse(pid, input, "PID")?,
+            negated,
+        })
+    }
+}
+
+impl fmt::Display for VidPidPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bang = if self.negated { "!" } else { "" };
+        write!(f, "{}{}:{}", bang, self.vid, self.pid)
+    }
+}
+
+/// A set of VID:PID patterns
+///
+/// A device matches when it matches at least one positive pattern (or
+/// there are none) and no negated pattern.
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::VidPidFilter;
+///
+/// // All FTDI devices except the FT232R
+/// let filter = VidPidFilter::parse_list(["0403:*", "!0403:6001"]).unwrap();
+/// assert!(filter.matches_ids(0x0403, 0x6014));
+/// assert!(!filter.matches_ids(0x0403, 0x6001));
+/// assert!(!filter.matches_ids(0x0483, 0x5740));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VidPidFilter {
+    patterns: Vec<VidPidPattern>,
+}
+
+impl VidPidFilter {
+    /// Create a filter that matches every device
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a list of patterns
+    pub fn parse_list<I, S>(patterns: I) -> Result<Self, VidPidError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|p| p.as_ref().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Add a pattern
+    pub fn push(&mut self, pattern: VidPidPattern) {
+        self.patterns.push(pattern);
+    }
+
+    /// Check whether a VID:PID pair passes the filter
+    pub fn matches_ids(&self, vid: u16, pid: u16) -> bool {
+        let mut positives = self.patterns.iter().filter(|p| !p.negated).peekable();
+        let included = positives.peek().is_none() || positives.any(|p| p.matches_ids(vid, pid));
+        included
+            && !self
+                .patterns
+                .iter()
+                .any(|p| p.negated && p.matches_ids(vid, pid))
+    }
+
+    /// Check whether a device passes the filter
+    pub fn matches(&self, device: &UsbDevice) -> bool {
+        self.matches_ids(device.vid, device.pid)
+    }
+
+    /// The patterns in this filter
+    pub fn patterns(&self) -> &[VidPidPattern] {
+        &self.patterns
+    }
+
+    /// Check if the filter has no patterns (and so matches everything)
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+impl FromStr for VidPidFilter {
+    type Err = VidPidError;
+
+    /// Parse a comma-separated list of patterns
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_list(s.split(',').filter(|p| !p.trim().is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_patterns() {
+        let p: VidPidPattern = "1234:*".parse().unwrap();
+        assert_eq!(p.vid, IdMatch::Exact(0x1234));
+        assert_eq!(p.pid, IdMatch::Any);
+
+        let p: VidPidPattern = "!1234:0100-01ff".parse().unwrap();
+        assert!(p.negated);
+        assert_eq!(p.pid, IdMatch::Range(0x0100, 0x01ff));
+
+        let p: VidPidPattern = "0x1234:5600/ff00".parse().unwrap();
+        assert_eq!(p.pid, IdMatch::Mask { value: 0x5600, mask: 0xff00 });
+        assert_eq!(p.to_string(), "1234:5600/ff00");
+    }
+
+    #[test]
+    fn test_mask_and_range() {
+        let p: VidPidPattern = "1234:5600/ff00".parse().unwrap();
+        assert!(p.matches_ids(0x1234, 0x56ab));
+        assert!(!p.matches_ids(0x1234, 0x57ab));
+
+        let p: VidPidPattern = "*:0100-01ff".parse().unwrap();
+        assert!(p.matches_ids(0xffff, 0x01ff));
+        assert!(!p.matches_ids(0xffff, 0x0200));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "12345678".parse::<VidPidPattern>().unwrap_err(),
+            VidPidError::MissingColon("12345678".to_string())
+        );
+        assert!(matches!(
+            "1234:01ff-0100".parse::<VidPidPattern>().unwrap_err(),
+            VidPidError::EmptyRange { .. }
+        ));
+        assert!(matches!(
+            "xyz:*".parse::<VidPidPattern>().unwrap_err(),
+            VidPidError::InvalidId { part: "VID", .. }
+        ));
+        for input in ["+403:6001", "0403:+6001", "0403:+1-2", "0403:6001/+ff", "0x+403:*"] {
+            assert!(
+                matches!(input.parse::<VidPidPattern>(), Err(VidPidError::InvalidId { .. })),
+                "{input} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_only_negations() {
+        let filter: VidPidFilter = "!1d6b:*".parse().unwrap();
+        assert!(filter.matches_ids(0x0483, 0x5740));
+        assert!(!filter.matches_ids(0x1d6b, 0x0002));
+    }
+}
@@ -0,0 +1,219 @@
+//! Ordered traversal of device trees
+
+use std::collections::VecDeque;
+
+use crate::path::DevicePath;
+use crate::tree::PortTree;
+
+/// Order in which a [`Traverse`] iterator visits nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Each node before its children, children in port order
+    DepthFirst,
+    /// All nodes of one depth before the next, in path order
+    BreadthFirst,
+}
+
+/// Iterator over the values of one or more port trees
+///
+/// Yields `(path, depth, value)`, where depth counts from the node the
+/// traversal started at. Nodes without a value (hubs that are not in the
+/// tree) are skipped, but their children are still visited.
+#[derive(Debug)]
+pub struct Traverse<'a, T> {
+    pending: VecDeque<(DevicePath, usize, &'a PortTree<T>)>,
+    order: Order,
+}
+
+impl<'a, T> Traverse<'a, T> {
+    /// Traverse the given subtrees in order
+    pub fn new(roots: impl IntoIterator<Item = (DevicePath, &'a PortTree<T>)>, order: Order) -> Self {
+        Self {
+            pending: roots.into_iter().map(|(path, node)| (path, 0, node)).collect(),
+            order,
+        }
+    }
+}
+
+impl<'a, T> Iterator for Traverse<'a, T> {
+    type Item = (DevicePath, usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, depth, node)) = self.pending.pop_front() {
+            let children = node
+                .children
+                .iter()
+                .map(|(&port, child)| (path.child(port), depth + 1, child));
+            match self.order {
+                Order::DepthFirst => {
+                    for child in children.rev() {
+                        self.pending.push_front(child);
+                    }
+                }
+                Order::BreadthFirst => self.pending.extend(children),
+            }
+            if let Some(ref value) = node.value {
+                return Some((path, depth, value));
+            }
+        }
+        None
+    }
+}
+
+/// A node passed to a [`Visitor`]
+#[derive(Debug)]
+pub struct VisitNode<'a, T> {
+    /// Path of the node
+    pub path: DevicePath,
+    /// Distance from the node the walk started at
+    pub depth: usize,
+    /// Value at the node, if any
+    pub value: Option<&'a T>,
+    /// Whether this is the last visited child of its parent
+    pub is_last: bool,
+}
+
+/// Callbacks for a depth-first walk over a device tree
+///
+/// For every node, `enter` is called before its children are walked and
+/// `leave` after. Before entering a node's children, `should_visit` is
+/// asked about each of them; skipped subtrees are not walked and do not
+/// count when deciding `is_last`.
+pub trait Visitor<T> {
+    /// Decide whether to walk the subtree at `path`, given all values in it
+    fn should_visit<'v>(&mut self, _path: &DevicePath, _values: impl Iterator<Item = &'v T>) -> bool
+    where
+        T: 'v, {
+        true
+    }
+
+    /// Called before a node's children are walked
+    fn enter(&mut self, _node: &VisitNode<'_, T>) {}
+
+    /// Called after a node's children are walked
+    fn leave(&mut self, _node: &VisitNode<'_, T>) {}
+}
+
+/// Walk a port tree, mapping node values through `resolve`
+///
+/// Returns `false` if the visitor skipped the root.
+pub(crate) fn walk<'a, U, T: 'a, V: Visitor<T>>(
+    node: &'a PortTree<U>,
+    path: DevicePath,
+    resolve: &impl Fn(&'a U) -> Option<&'a T>,
+    visitor: &mut V,
+) -> bool {
+    if !visitor.should_visit(&path, subtree_values(node, &path, resolve)) {
+        return false;
+    }
+    walk_node(node, path, 0, true, resolve, visitor);
+    true
+}
+
+fn subtree_values<'a, U, T: 'a>(
+    node: &'a PortTree<U>,
+    path: &DevicePath,
+    resolve: &impl Fn(&'a U) -> Option<&'a T>,
+) -> impl Iterator<Item = &'a T> {
+    Traverse::new([(path.clone(), node)], Order::DepthFirst)
+        .filter_map(|(_, _, value)| resolve(value))
+}
+
+fn walk_node<'a, U, T: 'a, V: Visitor<T>>(
+    node: &'a PortTree<U>,
+    path: DevicePath,
+    depth: usize,
+    is_last: bool,
+    resolve: &impl Fn(&'a U) -> Option<&'a T>,
+    visitor: &mut V,
+) {
+    let visit = VisitNode {
+        value: node.value.as_ref().and_then(resolve),
+        path,
+        depth,
+        is_last,
+    };
+    visitor.enter(&visit);
+
+    let children: Vec<(DevicePath, &PortTree<U>)> = node
+        .children
+        .iter()
+        .map(|(&port, child)| (visit.path.child(port), child))
+        .filter(|(child_path, child)| {
+            visitor.should_visit(child_path, subtree_values(child, child_path, resolve))
+        })
+        .collect();
+    let count = children.len();
+    for (i, (child_path, child)) in children.into_iter().enumerate() {
+        walk_node(child, child_path, depth + 1, i + 1 == count, resolve, visitor);
+    }
+
+    visitor.leave(&visit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> PortTree<&'static str> {
+        let mut tree = PortTree::new();
+        tree.insert(&[], "root");
+        tree.insert(&[2], "a");
+        tree.insert(&[2, 1], "a1");
+        tree.insert(&[10], "b");
+        tree.insert(&[1, 3], "c3");
+        tree
+    }
+
+    fn names(order: Order) -> Vec<(String, usize, &'static str)> {
+        let tree = tree();
+        Traverse::new([(DevicePath::bus_only(1), &tree)], order)
+            .map(|(path, depth, name)| (path.to_string(), depth, *name))
+            .collect()
+    }
+
+    #[test]
+    fn test_depth_first() {
+        let expected = [
+            ("1:", 0, "root"),
+            ("1:1.3", 2, "c3"),
+            ("1:2", 1, "a"),
+            ("1:2.1", 2, "a1"),
+            ("1:10", 1, "b"),
+        ];
+        let expected: Vec<_> = expected.iter().map(|&(p, d, n)| (p.to_string(), d, n)).collect();
+        assert_eq!(names(Order::DepthFirst), expected);
+    }
+
+    #[test]
+    fn test_breadth_first() {
+        let order: Vec<&str> = names(Order::BreadthFirst).into_iter().map(|(_, _, n)| n).collect();
+        assert_eq!(order, ["root", "a", "b", "c3", "a1"]);
+    }
+
+    #[test]
+    fn test_visitor_skips_and_marks_last() {
+        #[derive(Default)]
+        struct Log(Vec<String>);
+
+        impl Visitor<&'static str> for Log {
+            fn should_visit<'v>(&mut self, _: &DevicePath, mut values: impl Iterator<Item = &'v &'static str>) -> bool {
+                values.any(|name| name.starts_with('a') || *name == "root")
+            }
+
+            fn enter(&mut self, node: &VisitNode<'_, &'static str>) {
+                let name = node.value.copied().unwrap_or("-");
+                self.0.push(format!("+{}{}", name, if node.is_last { "$" } else { "" }));
+            }
+
+            fn leave(&mut self, node: &VisitNode<'_, &'static str>) {
+                self.0.push(format!("-{}", node.value.copied().unwrap_or("-")));
+            }
+        }
+
+        let tree = tree();
+        let mut log = Log::default();
+        walk(&tree, DevicePath::bus_only(1), &|v| Some(v), &mut log);
+        assert_eq!(log.0, ["+root$", "+a$", "+a1$", "-a1", "-a", "-root"]);
+    }
+}
@@ -0,0 +1,217 @@
+//! Waiting for devices to appear or go away
+
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use futures_lite::future;
+
+use crate::device::UsbDevice;
+use crate::error::UsbTreeError;
+use crate::live::{EventSource, LiveTree, TreeChange};
+use crate::path::DevicePath;
+use crate::tree::UsbTree;
+
+/// How a wait ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaitOutcome {
+    /// The condition was met: the path of the device that appeared, or the
+    /// paths of the matching devices that went away while waiting
+    Met(Vec<DevicePath>),
+    /// The timeout expired first
+    TimedOut,
+    /// The event source ran out first, e.g. at the end of a replay
+    Ended,
+}
+
+/// Waits until a matching device is connected, or until none is left
+///
+/// Feed it the tree when waiting starts and then every change; it reports
+/// the paths once the condition holds. [`DeviceWait::run`] does both for
+/// an [`EventSource`].
+///
+/// # Examples
+///
+/// ```
+/// use usbinfo::{DeviceWait, Replay, WaitOutcome};
+///
+/// let recording = r#"
+/// {"type":"snapshot","timestamp":"2026-01-05T10:00:00Z","devices":[]}
+/// {"type":"attach","timestamp":"2026-01-05T10:00:02Z","device":{"bus":1,"port_path":[2],"vid":11914,"pid":3}}
+/// "#;
+/// let mut replay = Replay::from_reader(recording.as_bytes()).unwrap().with_speed(f64::INFINITY);
+///
+/// let wait = DeviceWait::appear(|_, device| device.vid == 0x2e8a);
+/// let outcome = futures_lite::future::block_on(wait.run(&mut replay, None)).unwrap();
+/// assert_eq!(outcome, WaitOutcome::Met(vec!["1:2".parse().unwrap()]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeviceWait<F> {
+    matches: F,
+    gone: bool,
+    left: Vec<DevicePath>,
+}
+
+impl<F: Fn(&DevicePath, &UsbDevice) -> bool> DeviceWait<F> {
+    /// Wait until a device at a path matching `matches` is connected
+    pub fn appear(matches: F) -> Self {
+        Self {
+            matches,
+            gone: false,
+            left: Vec::new(),
+        }
+    }
+
+    /// Wait until no device matching `matches` is left
+    pub fn gone(matches: F) -> Self {
+        Self {
+            gone: true,
+            ..Self::appear(matches)
+        }
+    }
+
+    /// Check the tree when waiting starts
+    ///
+    /// Returns the first matching path if one is already there, or no
+    /// paths if waiting for devices to go away and none match.
+    pub fn start(&mut self, tree: &UsbTree<UsbDevice>) -> Option<Vec<DevicePath>> {
+        let present = self.present(tree);
+        match (self.gone, present.first()) {
+            (false, Some(path)) => Some(vec![path.clone()]),
+            (true, None) => Some(Vec::new()),
+            _ => None,
+        }
+    }
+
+    /// Check a change, along with the tree after it
+    ///
+    /// A matching device replaced at its path by one that does not match
+    /// counts as gone.
+    pub fn change(&mut self, change: &TreeChange, tree: &UsbTree<UsbDevice>) -> Option<Vec<DevicePath>> {
+        let path = change.path();
+        let (before, after) = match change {
+            TreeChange::Added { device, .. } => (None, Some(device)),
+            TreeChange::Removed { device, .. } => (Some(device), None),
+            TreeChange::Changed { old, new, .. } => (Some(old), Some(new)),
+        };
+        let matched = |device: Option<&UsbDevice>| device.is_some_and(|d| (self.matches)(path, d));
+
+        if !self.gone {
+            return matched(after).then(|| vec![path.clone()]);
+        }
+        if matched(before) && !matched(after) {
+            self.left.push(path.clone());
+        }
+        self.present(tree).is_empty().then(|| mem::take(&mut self.left))
+    }
+
+    /// Follow `source` until the condition holds, the timeout expires or
+    /// the source runs out
+    ///
+    /// The timeout counts the source's own time: the wall-clock deadline is
+    /// divided by its [`speed`](EventSource::speed), and its timestamps are
+    /// checked too, so a fast or instant replay times out at the recorded
+    /// time.
+    pub async fn run(
+        mut self,
+        source: &mut impl EventSource,
+        timeout: Option<Duration>,
+    ) -> Result<WaitOutcome, UsbTreeError> {
+        let live = LiveTree::new();
+        live.sync(source.snapshot().await?);
+        if let Some(paths) = self.start(&live.read()) {
+            return Ok(WaitOutcome::Met(paths));
+        }
+
+        // Sleep on the blocking pool, so the deadline can race the next event
+        let started = source.timestamp();
+        let speed = source.speed();
+        let mut deadline = timeout.filter(|_| speed.is_finite()).map(|timeout| {
+            let timeout = timeout.div_f64(speed);
+            blocking::unblock(move || thread::sleep(timeout))
+        });
+        loop {
+            let next = async { Some(source.next_event().await) };
+            let timed_out = async {
+                match deadline {
+                    Some(ref mut deadline) => deadline.await,
+                    None => future::pending().await,
+                }
+                None
+            };
+            let event = match future::or(next, timed_out).await {
+                Some(Some(event)) => event,
+                Some(None) => return Ok(WaitOutcome::Ended),
+                None => return Ok(WaitOutcome::TimedOut),
+            };
+            let elapsed = source.timestamp().duration_since(started).unwrap_or_default();
+            if timeout.is_some_and(|timeout| elapsed > timeout) {
+                return Ok(WaitOutcome::TimedOut);
+            }
+            for change in live.apply(event) {
+                if let Some(paths) = self.change(&change, &live.read()) {
+                    return Ok(WaitOutcome::Met(paths));
+                }
+            }
+        }
+    }
+
+    /// Paths of the matching devices in a tree
+    fn present(&self, tree: &UsbTree<UsbDevice>) -> Vec<DevicePath> {
+        tree.all_devices()
+            .filter(|(path, device)| (self.matches)(path, device))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+    use crate::recording::Replay;
+
+    const RECORDING: &str = r#"
+{"type":"snapshot","timestamp":"2026-01-05T10:00:00Z","devices":[{"bus":1,"port_path":[1],"vid":1027,"pid":24577,"serial":"A"},{"bus":1,"port_path":[2],"vid":1027,"pid":24577,"serial":"B"}]}
+{"type":"detach","timestamp":"2026-01-05T10:00:01Z","path":"1:1"}
+{"type":"attach","timestamp":"2026-01-05T10:00:02Z","device":{"bus":1,"port_path":[2],"vid":1027,"pid":24577,"serial":"C"}}
+{"type":"attach","timestamp":"2026-01-05T10:00:05Z","device":{"bus":1,"port_path":[3],"vid":11914,"pid":3}}
+"#;
+
+    fn run<F: Fn(&DevicePath, &UsbDevice) -> bool>(wait: DeviceWait<F>, timeout: Option<u64>) -> WaitOutcome {
+        let mut replay = Replay::from_reader(RECORDING.as_bytes()).unwrap().with_speed(f64::INFINITY);
+        block_on(wait.run(&mut replay, timeout.map(Duration::from_secs))).unwrap()
+    }
+
+    fn met(paths: &[&str]) -> WaitOutcome {
+        WaitOutcome::Met(paths.iter().map(|p| p.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn test_wait_for_device() {
+        let ftdi = |_: &DevicePath, d: &UsbDevice| d.vid == 0x0403;
+        assert_eq!(run(DeviceWait::appear(ftdi), None), met(&["1:1"]));
+
+        let pico = |_: &DevicePath, d: &UsbDevice| d.vid == 0x2e8a;
+        assert_eq!(run(DeviceWait::appear(pico), None), met(&["1:3"]));
+        assert_eq!(run(DeviceWait::appear(pico), Some(3)), WaitOutcome::TimedOut);
+
+        let nothing = |_: &DevicePath, _: &UsbDevice| false;
+        assert_eq!(run(DeviceWait::appear(nothing), None), WaitOutcome::Ended);
+    }
+
+    #[test]
+    fn test_wait_until_gone() {
+        // B is replaced by C at 1:2, which counts as B going away
+        let serial = |d: &UsbDevice| d.serial.as_deref().is_some_and(|s| s == "A" || s == "B");
+        let wait = DeviceWait::gone(move |_: &DevicePath, d: &UsbDevice| serial(d));
+        assert_eq!(run(wait, None), met(&["1:1", "1:2"]));
+
+        let wait = DeviceWait::gone(|_: &DevicePath, d: &UsbDevice| d.vid == 0x0403);
+        assert_eq!(run(wait, Some(10)), WaitOutcome::Ended);
+
+        let pico = |_: &DevicePath, d: &UsbDevice| d.vid == 0x2e8a;
+        assert_eq!(run(DeviceWait::gone(pico), None), met(&[]));
+    }
+}